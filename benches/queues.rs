@@ -0,0 +1,137 @@
+// Throughput benchmarks for the queues in canal.
+//
+// These use a plain harness so they run on stable: `cargo bench`.
+
+extern crate canal;
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use canal::mpmc::{LockFreeQueue, MutexLinkedList};
+use canal::spsc::spsc_channel;
+
+const MSGS: usize = 1_000_000;
+const CAPACITY: usize = 1024;
+const RUNS: usize = 5;
+const THREADS: usize = 4;
+
+// Run `f` RUNS times and report the best time per message.
+fn bench<F: FnMut()>(name: &str, mut f: F) {
+    let mut best = None;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        f();
+        let elapsed = start.elapsed();
+        if best.is_none_or(|b| elapsed < b) {
+            best = Some(elapsed);
+        }
+    }
+
+    let nanos = best.unwrap().as_nanos() as f64 / MSGS as f64;
+    println!("{:<40} {:>8.2} ns/msg", name, nanos);
+}
+
+fn spsc_one_to_one() {
+    let (p, c) = spsc_channel(CAPACITY);
+    let producer = thread::spawn(move || {
+        for i in 0..MSGS {
+            let mut value = i;
+            while let Err(v) = p.push(value) {
+                value = v;
+                thread::yield_now();
+            }
+        }
+    });
+
+    for _ in 0..MSGS {
+        while c.pop().is_none() {
+            thread::yield_now();
+        }
+    }
+    producer.join().unwrap();
+}
+
+fn lock_free_queue_one_to_one() {
+    let q = Arc::new(LockFreeQueue::with_capacity(CAPACITY));
+    let producer = {
+        let q = q.clone();
+        thread::spawn(move || {
+            for i in 0..MSGS {
+                let mut value = i;
+                while let Err(v) = q.push(value) {
+                    value = v;
+                    thread::yield_now();
+                }
+            }
+        })
+    };
+
+    for _ in 0..MSGS {
+        while q.pop().is_none() {
+            thread::yield_now();
+        }
+    }
+    producer.join().unwrap();
+}
+
+fn lock_free_queue_contended() {
+    let q = Arc::new(LockFreeQueue::with_capacity(CAPACITY));
+    let per_thread = MSGS / THREADS;
+
+    let producers = (0..THREADS).map(|_| {
+        let q = q.clone();
+        thread::spawn(move || {
+            for i in 0..per_thread {
+                let mut value = i;
+                while let Err(v) = q.push(value) {
+                    value = v;
+                    thread::yield_now();
+                }
+            }
+        })
+    }).collect::<Vec<_>>();
+
+    let consumers = (0..THREADS).map(|_| {
+        let q = q.clone();
+        thread::spawn(move || {
+            for _ in 0..per_thread {
+                while q.pop().is_none() {
+                    thread::yield_now();
+                }
+            }
+        })
+    }).collect::<Vec<_>>();
+
+    for thr in producers.into_iter().chain(consumers) {
+        thr.join().unwrap();
+    }
+}
+
+fn linked_list_one_to_one(pool_size: usize) {
+    let q = MutexLinkedList::new();
+    q.set_pool_size(pool_size);
+    let producer = {
+        let q = q.clone();
+        thread::spawn(move || {
+            for i in 0..MSGS {
+                q.push(i).unwrap();
+            }
+        })
+    };
+
+    for _ in 0..MSGS {
+        while q.pop().is_none() {
+            thread::yield_now();
+        }
+    }
+    producer.join().unwrap();
+}
+
+fn main() {
+    bench("spsc::spsc_channel 1p/1c", spsc_one_to_one);
+    bench("mpmc::LockFreeQueue 1p/1c", lock_free_queue_one_to_one);
+    bench("mpmc::LockFreeQueue 4p/4c", lock_free_queue_contended);
+    bench("mpmc::MutexLinkedList 1p/1c", || linked_list_one_to_one(64));
+    bench("mpmc::MutexLinkedList 1p/1c, no pool", || linked_list_one_to_one(0));
+}
@@ -0,0 +1,395 @@
+// A broadcast over a single ring buffer shared by every consumer. Each message
+// is stored once, and each consumer keeps its own cursor into the buffer. The
+// sender never waits on a slow consumer, it overwrites the oldest message and
+// the consumer finds out how much it missed the next time it receives.
+
+use std::cell::Cell;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use broadcast::BroadcastError;
+
+struct Ring<T> {
+    slots: Vec<Option<T>>,
+    // The sequence number of the next message sent. Message `n` lives in slot
+    // `n % slots.len()` until it is overwritten.
+    next: u64,
+    consumers: usize,
+    // The number of BoundedBroadcast handles. Consumers are disconnected once
+    // this drops to 0.
+    broadcasters: usize,
+    // Set by `close`.
+    closed: bool,
+}
+
+impl<T> Ring<T> {
+    fn is_closed(&self) -> bool {
+        self.closed || self.broadcasters == 0
+    }
+
+    // The sequence number of the oldest message still in the buffer.
+    fn oldest(&self) -> u64 {
+        self.next.saturating_sub(self.slots.len() as u64)
+    }
+}
+
+struct Shared<T> {
+    ring: Mutex<Ring<T>>,
+    // Signalled when a message is sent or the broadcast is closed.
+    cond: Condvar,
+}
+
+impl<T> Shared<T> {
+    // A panicking `T::clone` in `take` poisons the lock, but leaves the ring
+    // consistent, so the poison is ignored.
+    fn lock(&self) -> MutexGuard<'_, Ring<T>> {
+        self.ring.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Add a consumer, returning the cursor it starts from.
+    fn subscribe(&self) -> u64 {
+        let mut ring = self.lock();
+        ring.consumers += 1;
+        ring.next
+    }
+}
+
+/// Sends messages to every BoundedConsumer through a fixed size buffer.
+///
+/// Each message is stored once, however many consumers there are. A consumer
+/// that falls more than the capacity behind misses the oldest messages and is
+/// told so with `BroadcastError::Lagged`.
+///
+/// A BoundedBroadcast can be cloned to send from several places. Consumers are
+/// disconnected once every clone has been dropped.
+pub struct BoundedBroadcast<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> BoundedBroadcast<T> {
+    /// Create a new BoundedBroadcast that keeps the last `cap` messages.
+    ///
+    /// Panics if `cap` is 0.
+    pub fn new(cap: usize) -> BoundedBroadcast<T> {
+        assert!(cap > 0, "capacity must be greater than 0");
+        let ring = Ring {
+            slots: (0..cap).map(|_| None).collect(),
+            next: 0,
+            consumers: 0,
+            broadcasters: 1,
+            closed: false,
+        };
+        BoundedBroadcast {
+            shared: Arc::new(Shared { ring: Mutex::new(ring), cond: Condvar::new() }),
+        }
+    }
+
+    /// Returns the number of messages kept for consumers.
+    pub fn capacity(&self) -> usize {
+        self.shared.lock().slots.len()
+    }
+
+    /// Create a BoundedConsumer that receives every message sent from now on.
+    pub fn consume(&self) -> BoundedConsumer<T> {
+        let cursor = self.shared.subscribe();
+        BoundedConsumer { shared: self.shared.clone(), cursor: Cell::new(cursor) }
+    }
+
+    /// Send a message to every consumer.
+    ///
+    /// This never blocks. If the buffer is full, the oldest message is
+    /// overwritten. Returns the number of consumers the message was sent to. If
+    /// there are none, the message is returned in the Err(..).
+    pub fn send(&self, data: T) -> Result<usize, BroadcastError<T>> {
+        let mut ring = self.shared.lock();
+        if ring.consumers == 0 || ring.closed {
+            return Err(BroadcastError::SendError(data))
+        }
+
+        let slot = (ring.next % ring.slots.len() as u64) as usize;
+        ring.slots[slot] = Some(data);
+        ring.next += 1;
+        self.shared.cond.notify_all();
+        Ok(ring.consumers)
+    }
+
+    /// Close the broadcast for every handle.
+    ///
+    /// Consumers still receive the messages kept in the buffer, after which
+    /// they are disconnected. Any further send fails.
+    pub fn close(&self) {
+        self.shared.lock().closed = true;
+        self.shared.cond.notify_all();
+    }
+
+    /// Returns the number of consumers that can still receive new messages.
+    ///
+    /// Consumers that were dropped are not counted, and none are once the
+    /// broadcast is closed. `send` fails exactly when this is 0.
+    pub fn consumer_count(&self) -> usize {
+        let ring = self.shared.lock();
+        if ring.closed { 0 } else { ring.consumers }
+    }
+}
+
+impl<T> Clone for BoundedBroadcast<T> {
+    fn clone(&self) -> BoundedBroadcast<T> {
+        self.shared.lock().broadcasters += 1;
+        BoundedBroadcast { shared: self.shared.clone() }
+    }
+}
+
+impl<T> Drop for BoundedBroadcast<T> {
+    fn drop(&mut self) {
+        let mut ring = self.shared.lock();
+        ring.broadcasters -= 1;
+        if ring.broadcasters == 0 {
+            self.shared.cond.notify_all();
+        }
+    }
+}
+
+/// Receives messages from a BoundedBroadcast.
+pub struct BoundedConsumer<T> {
+    shared: Arc<Shared<T>>,
+    // The sequence number of the next message to receive.
+    cursor: Cell<u64>,
+}
+
+impl<T: Clone> BoundedConsumer<T> {
+    /// Receive the next message from the BoundedBroadcast.
+    ///
+    /// This function will block. If messages were overwritten before this
+    /// consumer got to them, `BroadcastError::Lagged` is returned with the
+    /// number of messages skipped, and the next call receives the oldest
+    /// message still kept. Once the broadcast is closed, or every broadcaster is
+    /// dropped, and every message has been received, `BroadcastError::Disconnected` is returned.
+    pub fn recv(&self) -> Result<T, BroadcastError<T>> {
+        let mut ring = self.shared.lock();
+        loop {
+            if let Some(res) = self.take(&ring) {
+                return res
+            }
+            ring = self.shared.cond.wait(ring).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Receive the next message from the BoundedBroadcast without blocking.
+    ///
+    /// If there is no message waiting, `BroadcastError::Empty` is returned.
+    pub fn try_recv(&self) -> Result<T, BroadcastError<T>> {
+        let ring = self.shared.lock();
+        self.take(&ring).unwrap_or(Err(BroadcastError::Empty))
+    }
+
+    /// Receive the next message from the BoundedBroadcast, blocking for at most
+    /// `timeout`.
+    ///
+    /// If no message arrives in time, `BroadcastError::Timeout` is returned.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, BroadcastError<T>> {
+        self.recv_deadline(Instant::now() + timeout)
+    }
+
+    /// Receive the next message from the BoundedBroadcast, blocking until
+    /// `deadline` at the latest.
+    ///
+    /// If no message arrives in time, `BroadcastError::Timeout` is returned.
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, BroadcastError<T>> {
+        let mut ring = self.shared.lock();
+        loop {
+            if let Some(res) = self.take(&ring) {
+                return res
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(BroadcastError::Timeout)
+            }
+            ring = self.shared.cond.wait_timeout(ring, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner).0;
+        }
+    }
+
+    // Receive the message at the cursor, if there is one, or report a lag or
+    // disconnect. Returns None if the consumer has to wait.
+    fn take(&self, ring: &Ring<T>) -> Option<Result<T, BroadcastError<T>>> {
+        let cursor = self.cursor.get();
+        if cursor < ring.oldest() {
+            self.cursor.set(ring.oldest());
+            return Some(Err(BroadcastError::Lagged(ring.oldest() - cursor)))
+        }
+        if cursor < ring.next {
+            let slot = (cursor % ring.slots.len() as u64) as usize;
+            self.cursor.set(cursor + 1);
+            return Some(Ok(ring.slots[slot].clone().unwrap()))
+        }
+        if ring.is_closed() {
+            return Some(Err(BroadcastError::Disconnected))
+        }
+        None
+    }
+}
+
+impl<T> BoundedConsumer<T> {
+    /// Returns true if the broadcast has been closed, or every broadcaster has
+    /// been dropped.
+    ///
+    /// Messages sent before then may still be waiting to be received.
+    pub fn is_closed(&self) -> bool {
+        self.shared.lock().is_closed()
+    }
+}
+
+impl<T> Clone for BoundedConsumer<T> {
+    /// The new consumer receives every message sent from now on, not the
+    /// messages this one has yet to receive.
+    fn clone(&self) -> Self {
+        let cursor = self.shared.subscribe();
+        BoundedConsumer { shared: self.shared.clone(), cursor: Cell::new(cursor) }
+    }
+}
+
+impl<T> Drop for BoundedConsumer<T> {
+    fn drop(&mut self) {
+        self.shared.lock().consumers -= 1;
+    }
+}
+
+/// Create a (BoundedBroadcast<T>, BoundedConsumer<T>) pair that keeps the last
+/// `cap` messages.
+pub fn bounded_broadcast_channel<T: Clone>(cap: usize) -> (BoundedBroadcast<T>, BoundedConsumer<T>) {
+    let broadcast = BoundedBroadcast::new(cap);
+    let consumer = broadcast.consume();
+    (broadcast, consumer)
+}
+
+#[cfg(test)]
+mod test {
+    use broadcast::{bounded_broadcast_channel, BroadcastError};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::thread::spawn;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn lagged_consumer() {
+        let (p, c1) = bounded_broadcast_channel(4);
+        let c2 = c1.clone();
+        assert_eq!(p.capacity(), 4);
+
+        for i in 0..6u8 {
+            assert_eq!(p.send(i), Ok(2));
+        }
+        assert_eq!(c1.recv(), Err(BroadcastError::Lagged(2)));
+        for i in 2..6 {
+            assert_eq!(c1.recv(), Ok(i));
+        }
+
+        // A consumer that keeps up never lags.
+        assert_eq!(p.send(6), Ok(2));
+        assert_eq!(c1.recv(), Ok(6));
+        assert_eq!(c2.recv(), Err(BroadcastError::Lagged(3)));
+        assert_eq!(c2.recv(), Ok(3));
+
+        assert_eq!(p.consumer_count(), 2);
+        drop(c1);
+        drop(c2);
+        assert_eq!(p.consumer_count(), 0);
+        assert_eq!(p.send(7), Err(BroadcastError::SendError(7)));
+    }
+
+    #[test]
+    fn try_and_timed_recv() {
+        let (p, c) = bounded_broadcast_channel(2);
+        assert_eq!(c.try_recv(), Err(BroadcastError::Empty));
+        assert_eq!(c.recv_timeout(Duration::from_millis(10)), Err(BroadcastError::Timeout));
+
+        assert_eq!(p.send(1u8), Ok(1));
+        assert_eq!(c.try_recv(), Ok(1));
+        assert_eq!(p.send(2), Ok(1));
+        drop(p);
+        assert_eq!(c.recv_deadline(Instant::now()), Ok(2));
+        assert_eq!(c.try_recv(), Err(BroadcastError::Disconnected));
+    }
+
+    #[test]
+    fn close_and_count() {
+        let (p, c) = bounded_broadcast_channel(2);
+        let p2 = p.clone();
+        let c2 = c.clone();
+        assert_eq!(p.consumer_count(), 2);
+        drop(c2);
+        assert_eq!(p2.consumer_count(), 1);
+
+        assert_eq!(p.send(1u8), Ok(1));
+        assert!(!c.is_closed());
+        p2.close();
+        assert!(c.is_closed());
+        assert_eq!(p.consumer_count(), 0);
+        assert_eq!(p.send(2), Err(BroadcastError::SendError(2)));
+        assert_eq!(c.recv(), Ok(1));
+        assert_eq!(c.recv(), Err(BroadcastError::Disconnected));
+    }
+
+    #[test]
+    fn disconnect_after_drain() {
+        let (p, c) = bounded_broadcast_channel(2);
+        let receiver = spawn(move || {
+            assert_eq!(c.recv(), Ok(1u8));
+            assert_eq!(c.recv(), Ok(2));
+            assert_eq!(c.recv(), Err(BroadcastError::Disconnected));
+        });
+
+        assert_eq!(p.send(1), Ok(1));
+        assert_eq!(p.send(2), Ok(1));
+        drop(p);
+        receiver.join().unwrap();
+    }
+
+    #[test]
+    fn cloned_broadcasters() {
+        let (p1, c) = bounded_broadcast_channel(4);
+        let p2 = p1.clone();
+
+        assert_eq!(p1.send(1u8), Ok(1));
+        drop(p1);
+        assert!(!c.is_closed());
+        assert_eq!(p2.send(2), Ok(1));
+        let p3 = p2.clone();
+        drop(p2);
+        assert_eq!(p3.send(3), Ok(1));
+        drop(p3);
+
+        assert_eq!(c.recv(), Ok(1));
+        assert_eq!(c.recv(), Ok(2));
+        assert_eq!(c.recv(), Ok(3));
+        assert_eq!(c.recv(), Err(BroadcastError::Disconnected));
+    }
+
+    // Panics when 0 is cloned.
+    #[derive(PartialEq, Debug)]
+    struct Fussy(u8);
+
+    impl Clone for Fussy {
+        fn clone(&self) -> Fussy {
+            assert!(self.0 != 0, "cloned 0");
+            Fussy(self.0)
+        }
+    }
+
+    #[test]
+    fn panicking_clone() {
+        let (p, c) = bounded_broadcast_channel(4);
+        let c2 = c.clone();
+        assert_eq!(p.send(Fussy(0)), Ok(2));
+        assert_eq!(p.send(Fussy(1)), Ok(2));
+
+        // The consumer is dropped while its thread unwinds.
+        assert!(spawn(move || c2.recv()).join().is_err());
+        assert_eq!(p.consumer_count(), 1);
+
+        // The message that failed to clone is skipped.
+        assert!(catch_unwind(AssertUnwindSafe(|| c.recv())).is_err());
+        assert_eq!(c.recv(), Ok(Fussy(1)));
+        assert_eq!(p.send(Fussy(2)), Ok(1));
+        assert_eq!(c.recv_timeout(Duration::from_millis(10)), Ok(Fussy(2)));
+    }
+}
@@ -1,18 +1,44 @@
 //! A Single-Producer, Multiple-Consumer queue.
-
-use std::sync::mpsc::{channel, Receiver, RecvError, Sender, SendError};
-use std::sync::{Arc, Mutex, MutexGuard};
+//!
+//! `Broadcast` gives every consumer its own queue, unbounded unless the consumer
+//! is created with `Broadcast::consume_with`.
+//! `BoundedBroadcast` stores each message once in a fixed size buffer that all
+//! of its consumers read from. `SharedBroadcast` sends each message to every
+//! consumer as an `Arc`, for messages that are expensive or impossible to clone.
+
+pub use self::bounded::{bounded_broadcast_channel, BoundedBroadcast, BoundedConsumer};
+pub use self::shared::{shared_broadcast_channel, SharedBroadcast, SharedConsumer};
+
+mod bounded;
+mod shared;
+
+use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use std::fmt;
 use std::any::Any;
+use std::collections::VecDeque;
 use std::error::Error;
 
+use mpmc::MutexLinkedList;
+
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 /// Error from broadcast module.
 pub enum BroadcastError<T> {
     /// Send error
     SendError(T),
-    /// Receive error
-    RecvError,
+    /// There was no message to receive.
+    Empty,
+    /// No message arrived before the timeout.
+    Timeout,
+    /// Every sender is gone and there are no more messages to receive.
+    Disconnected,
+    /// The consumer fell behind and this many messages were skipped.
+    Lagged(u64),
+    /// There were no consumers, but the message was kept for consumers created
+    /// later.
+    Kept,
 }
 
 impl<T: fmt::Display> fmt::Display for BroadcastError<T> {
@@ -20,8 +46,16 @@ impl<T: fmt::Display> fmt::Display for BroadcastError<T> {
         match *self {
             BroadcastError::SendError(ref t) =>
                 write!(fmt, "could not send data on channel: {}", t),
-            BroadcastError::RecvError =>
-                write!(fmt, "could not receive data on channel"),
+            BroadcastError::Empty =>
+                write!(fmt, "channel is empty"),
+            BroadcastError::Timeout =>
+                write!(fmt, "timed out waiting on channel"),
+            BroadcastError::Disconnected =>
+                write!(fmt, "channel is disconnected"),
+            BroadcastError::Lagged(n) =>
+                write!(fmt, "consumer lagged behind and skipped {} messages", n),
+            BroadcastError::Kept =>
+                write!(fmt, "no consumers, data was kept for later ones"),
         }
     }
 }
@@ -31,11 +65,15 @@ impl<T: Send + fmt::Display + fmt::Debug + Any> Error for BroadcastError<T> {
     fn description(&self) -> &str {
         match *self {
             BroadcastError::SendError(_) => "could not send data on channel",
-            BroadcastError::RecvError => "could not receive data on channel",
+            BroadcastError::Empty => "channel is empty",
+            BroadcastError::Timeout => "timed out waiting on channel",
+            BroadcastError::Disconnected => "channel is disconnected",
+            BroadcastError::Lagged(_) => "consumer lagged behind and skipped messages",
+            BroadcastError::Kept => "no consumers, data was kept for later ones",
         }
     }
 
-    fn cause(&self) -> Option<&Error> {
+    fn cause(&self) -> Option<&dyn Error> {
         None
     }
 }
@@ -49,11 +87,45 @@ impl<T> From<SendError<T>> for BroadcastError<T> {
 
 impl<T> From<RecvError> for BroadcastError<T> {
     fn from(_err: RecvError) -> BroadcastError<T> {
-        BroadcastError::RecvError
+        BroadcastError::Disconnected
+    }
+}
+
+impl<T> From<TryRecvError> for BroadcastError<T> {
+    fn from(err: TryRecvError) -> BroadcastError<T> {
+        match err {
+            TryRecvError::Empty => BroadcastError::Empty,
+            TryRecvError::Disconnected => BroadcastError::Disconnected,
+        }
+    }
+}
+
+impl<T> From<RecvTimeoutError> for BroadcastError<T> {
+    fn from(err: RecvTimeoutError) -> BroadcastError<T> {
+        match err {
+            RecvTimeoutError::Timeout => BroadcastError::Timeout,
+            RecvTimeoutError::Disconnected => BroadcastError::Disconnected,
+        }
     }
 }
 
+/// What a Broadcast does when a consumer's queue is full.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum OverflowPolicy {
+    /// Wait until the consumer makes room. Every send blocks in the meantime.
+    Block,
+    /// Skip the consumer for the message being sent.
+    DropNewest,
+    /// Throw away the oldest message in the consumer's queue.
+    DropOldest,
+    /// Disconnect the consumer. It still receives what is already queued.
+    Disconnect,
+}
+
 /// Struct that sends message on a broadcast pattern.
+///
+/// A Broadcast can be cloned to send from several places. Consumers are
+/// disconnected once every clone has been dropped.
 pub struct Broadcast<T> {
     inner: Arc<Inner<T>>,
 }
@@ -61,74 +133,391 @@ pub struct Broadcast<T> {
 impl<T> Broadcast<T> {
     /// Create a new Broadcast struct.
     pub fn new() -> Broadcast<T> {
-        let inner = Arc::new(Inner { senders: Mutex::new(Vec::new()) });
-        Broadcast { inner: inner }
+        Broadcast { inner: Arc::new(Inner::new()) }
+    }
+
+    /// Close the broadcast for every handle.
+    ///
+    /// Consumers still receive the messages already sent to them, after which
+    /// they are disconnected. Any further send fails.
+    pub fn close(&self) {
+        self.inner.close()
+    }
+
+    /// Returns the number of consumers that can still receive new messages.
+    ///
+    /// Consumers that were dropped, or disconnected by `close` or by their
+    /// OverflowPolicy, are not counted. `send` fails exactly when this is 0.
+    pub fn consumer_count(&self) -> usize {
+        self.inner.live_senders().len()
     }
+}
 
+impl<T: Send> Broadcast<T> {
     /// Create a Consumer that listens to messages from the Broadcaster.
     pub fn consume(&self) -> Consumer<T> {
-        let (b, c) = channel();
-        self.inner.add_sender(b);
-        Consumer { inner: self.inner.clone(), receiver: c }
+        Consumer::new(self.inner.clone(), None, None, OverflowPolicy::Block)
+    }
+
+    /// Create a Consumer that queues at most `cap` messages, and follows
+    /// `policy` when a message is sent to it while its queue is full.
+    ///
+    /// Consumers with different policies can listen to the same Broadcast.
+    /// Clones of the consumer share its capacity and policy.
+    ///
+    /// Panics if `cap` is 0.
+    pub fn consume_with(&self, cap: usize, policy: OverflowPolicy) -> Consumer<T> {
+        assert!(cap > 0, "capacity must be greater than 0");
+        Consumer::new(self.inner.clone(), None, Some(cap), policy)
+    }
+
+    /// Create a Consumer that only receives the messages for which `pred`
+    /// returns true.
+    ///
+    /// The predicate runs when a message is sent, so other messages are never
+    /// queued for this consumer. Clones of the consumer share its predicate.
+    pub fn consume_filtered<F>(&self, pred: F) -> Consumer<T>
+        where F: Fn(&T) -> bool + Send + Sync + 'static
+    {
+        Consumer::new(self.inner.clone(), Some(Arc::new(pred)), None, OverflowPolicy::Block)
     }
 }
 
-impl<T: Clone> Broadcast<T> {
+impl<T> Default for Broadcast<T> {
+    fn default() -> Broadcast<T> {
+        Broadcast::new()
+    }
+}
+
+impl<T> Clone for Broadcast<T> {
+    fn clone(&self) -> Broadcast<T> {
+        self.inner.broadcasters.fetch_add(1, Ordering::SeqCst);
+        Broadcast { inner: self.inner.clone() }
+    }
+}
+
+impl<T> Drop for Broadcast<T> {
+    fn drop(&mut self) {
+        if self.inner.broadcasters.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.inner.close();
+        }
+    }
+}
+
+impl<T: Clone + Send> Broadcast<T> {
+    /// Create a new Broadcast that keeps the last `n` messages sent.
+    ///
+    /// Every new Consumer, including clones, first receives those messages and
+    /// then the live ones. Messages sent before any consumer exists are kept
+    /// as well.
+    pub fn with_replay(n: usize) -> Broadcast<T> {
+        let replay = Replay { history: VecDeque::with_capacity(n), len: n, clone: T::clone };
+        let inner = Inner { replay: Some(Mutex::new(replay)), ..Inner::new() };
+        Broadcast { inner: Arc::new(inner) }
+    }
+
     /// Send a message on the broadcast.
-    pub fn send(&self, data: T) -> Result<(), BroadcastError<T>> {
-        let guard = self.inner.read_senders();
-        for s in guard.iter() {
-            try!(s.send(data.clone()));
+    ///
+    /// Consumers that have been dropped are skipped and forgotten, every other
+    /// consumer still gets the message unless its filter rejects it or its
+    /// OverflowPolicy drops it. Returns the number of consumers the message was
+    /// sent to. If there are no consumers left, the message is returned in the
+    /// Err(..). A Broadcast created with `with_replay` keeps the message for
+    /// later consumers instead, and returns `BroadcastError::Kept`.
+    ///
+    /// This blocks while a consumer with `OverflowPolicy::Block` has a full
+    /// queue, and so do other sends on this Broadcast and its clones. Consumers
+    /// can still be created, cloned and dropped in the meantime, and `close`
+    /// ends the wait.
+    pub fn send(&self, data: T) -> Result<usize, BroadcastError<T>> {
+        let _sending = self.inner.sending.lock().unwrap_or_else(PoisonError::into_inner);
+
+        // Take a snapshot of the consumers, so that neither the filters nor a
+        // blocking push run with the list locked. A consumer added from now on
+        // gets this message from the replay history, if there is one.
+        let subscribers = {
+            let senders = self.inner.live_senders();
+            if self.inner.closed.load(Ordering::SeqCst) {
+                return Err(BroadcastError::SendError(data))
+            }
+            if let Some(ref replay) = self.inner.replay {
+                replay.lock().unwrap_or_else(PoisonError::into_inner).record(&data);
+            }
+            senders.clone()
+        };
+        if subscribers.is_empty() {
+            if self.inner.replay.is_some() {
+                return Err(BroadcastError::Kept)
+            }
+            return Err(BroadcastError::SendError(data))
+        }
+
+        let mut sent = 0;
+        for s in subscribers.iter().filter(|s| s.wants(&data)) {
+            match s.send(data.clone()) {
+                Delivery::Sent => sent += 1,
+                // A consumer that is gone has its queue closed, and is forgotten
+                // the next time the list is locked.
+                Delivery::Dropped | Delivery::Gone => {}
+            }
+        }
+        Ok(sent)
+    }
+}
+
+// Decides which messages a filtered consumer receives.
+type Filter<T> = dyn Fn(&T) -> bool + Send + Sync;
+
+// What happened to a message sent to one consumer.
+#[derive(PartialEq, Eq, Debug)]
+enum Delivery {
+    Sent,
+    // The consumer's queue was full and its policy is DropNewest.
+    Dropped,
+    // The consumer is gone or was disconnected, and should be forgotten.
+    Gone,
+}
+
+// The sending side of one consumer's queue. The queue is closed when the
+// consumer is dropped or disconnected.
+struct Subscriber<T> {
+    queue: MutexLinkedList<T>,
+    filter: Option<Arc<Filter<T>>>,
+    policy: OverflowPolicy,
+}
+
+impl<T> Subscriber<T> {
+    fn new(queue: MutexLinkedList<T>, filter: Option<Arc<Filter<T>>>,
+           policy: OverflowPolicy) -> Subscriber<T> {
+        Subscriber { queue, filter, policy }
+    }
+
+    fn wants(&self, data: &T) -> bool {
+        self.filter.as_ref().is_none_or(|f| f(data))
+    }
+}
+
+impl<T: Send> Subscriber<T> {
+    fn send(&self, data: T) -> Delivery {
+        match self.policy {
+            OverflowPolicy::Block => match self.queue.push_wait(data) {
+                Ok(()) => Delivery::Sent,
+                Err(_) => Delivery::Gone,
+            },
+            OverflowPolicy::DropNewest => match self.queue.push(data) {
+                Ok(()) => Delivery::Sent,
+                Err(_) if self.queue.is_closed() => Delivery::Gone,
+                Err(_) => Delivery::Dropped,
+            },
+            OverflowPolicy::DropOldest => self.push_dropping_oldest(data),
+            OverflowPolicy::Disconnect => match self.queue.push(data) {
+                Ok(()) => Delivery::Sent,
+                Err(_) => {
+                    self.queue.close();
+                    Delivery::Gone
+                }
+            },
         }
+    }
 
-        Ok(())
+    fn push_dropping_oldest(&self, mut data: T) -> Delivery {
+        loop {
+            match self.queue.push(data) {
+                Ok(()) => return Delivery::Sent,
+                Err(_) if self.queue.is_closed() => return Delivery::Gone,
+                Err(d) => {
+                    data = d;
+                    self.queue.pop();
+                }
+            }
+        }
     }
 }
 
+// Consumers keep this alive too, so the senders are dropped by `close` rather
+// than along with the Inner.
 struct Inner<T> {
-    senders: Mutex<Vec<Sender<T>>>,
+    senders: Mutex<Vec<Arc<Subscriber<T>>>>,
+    // Held for the whole of a send, so that every consumer receives messages
+    // in the same order. Taken before `senders`, never while holding it.
+    sending: Mutex<()>,
+    // The number of Broadcast handles.
+    broadcasters: AtomicUsize,
+    // Only changed with `senders` locked.
+    closed: AtomicBool,
+    // Only locked with `senders` locked, so new consumers see neither a gap
+    // nor a repeat between the replayed and the live messages.
+    replay: Option<Mutex<Replay<T>>>,
+}
+
+// The most recent messages, for consumers that join late.
+struct Replay<T> {
+    history: VecDeque<T>,
+    len: usize,
+    // T::clone, kept here so that consume does not need a T: Clone bound.
+    clone: fn(&T) -> T,
+}
+
+impl<T> Replay<T> {
+    fn record(&mut self, data: &T) {
+        if self.len == 0 {
+            return
+        }
+        if self.history.len() == self.len {
+            self.history.pop_front();
+        }
+        self.history.push_back((self.clone)(data));
+    }
 }
 
 impl<T> Inner<T> {
-    fn read_senders<'a>(&'a self) -> MutexGuard<'a, Vec<Sender<T>>> {
-        self.senders.lock().unwrap()
+    fn new() -> Inner<T> {
+        Inner {
+            senders: Mutex::new(Vec::new()),
+            sending: Mutex::new(()),
+            broadcasters: AtomicUsize::new(1),
+            closed: AtomicBool::new(false),
+            replay: None,
+        }
+    }
+
+    // Replaying runs T::clone with the list locked. If it panics, the list
+    // itself is still sound, so poisoning is ignored.
+    fn read_senders<'a>(&'a self) -> MutexGuard<'a, Vec<Arc<Subscriber<T>>>> {
+        self.senders.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Lock the senders, forgetting the queues of consumers that were dropped or
+    // disconnected.
+    fn live_senders(&self) -> MutexGuard<'_, Vec<Arc<Subscriber<T>>>> {
+        let mut vec = self.read_senders();
+        vec.retain(|s| !s.queue.is_closed());
+        vec
     }
 
-    fn add_sender(&self, sender: Sender<T>) {
-        let mut vec = self.senders.lock().unwrap();
-        vec.push(sender);
+    // Close every queue and forget the senders, so consumers are disconnected
+    // once they have received what is already queued.
+    fn close(&self) {
+        let mut vec = self.read_senders();
+        self.closed.store(true, Ordering::SeqCst);
+        for sender in vec.drain(..) {
+            sender.queue.close();
+        }
+    }
+}
+
+impl<T: Send> Inner<T> {
+    // A consumer added after the Inner is closed has its queue closed right
+    // away, so it is disconnected from the start.
+    fn add_sender(&self, sender: Subscriber<T>) {
+        let mut vec = self.read_senders();
+        if let Some(ref replay) = self.replay {
+            // Replaying must not block or disconnect a consumer nobody is
+            // receiving from yet, so whatever the policy, only the newest
+            // messages that fit are kept.
+            let replay = replay.lock().unwrap_or_else(PoisonError::into_inner);
+            for data in replay.history.iter().filter(|data| sender.wants(data)) {
+                sender.push_dropping_oldest((replay.clone)(data));
+            }
+        }
+        if self.closed.load(Ordering::SeqCst) {
+            sender.queue.close();
+        } else {
+            vec.push(Arc::new(sender));
+        }
     }
 }
 
 /// Struct that receives messages from Broadcast.
 pub struct Consumer<T> {
     inner: Arc<Inner<T>>,
-    receiver: Receiver<T>,
+    queue: MutexLinkedList<T>,
+    filter: Option<Arc<Filter<T>>>,
+    cap: Option<usize>,
+    policy: OverflowPolicy,
 }
 
 impl<T> Consumer<T> {
+    /// Returns true if the broadcast has been closed, every broadcaster has
+    /// been dropped, or this consumer was disconnected for falling behind.
+    ///
+    /// Messages sent before then may still be waiting to be received.
+    pub fn is_closed(&self) -> bool {
+        self.queue.is_closed()
+    }
+}
+
+impl<T: Send> Consumer<T> {
+    fn new(inner: Arc<Inner<T>>, filter: Option<Arc<Filter<T>>>, cap: Option<usize>,
+           policy: OverflowPolicy) -> Consumer<T> {
+        let queue = match cap {
+            Some(cap) => MutexLinkedList::with_capacity(cap),
+            None => MutexLinkedList::new(),
+        };
+        inner.add_sender(Subscriber::new(queue.clone(), filter.clone(), policy));
+        Consumer { inner, queue, filter, cap, policy }
+    }
+
+    // Called when the queue is empty. It may have been closed just after a
+    // last message was pushed, so look once more.
+    fn empty_or_disconnected(&self, empty: BroadcastError<T>) -> Result<T, BroadcastError<T>> {
+        if !self.queue.is_closed() {
+            return Err(empty)
+        }
+        self.queue.pop().ok_or(BroadcastError::Disconnected)
+    }
+
     /// Receive a message from the Broadcast.
     ///
     /// This function will block.
     pub fn recv(&self) -> Result<T, BroadcastError<T>> {
-        let data = try!(self.receiver.recv());
-        Ok(data)
+        self.queue.pop_wait().ok_or(BroadcastError::Disconnected)
+    }
+
+    /// Receive a message from the Broadcast without blocking.
+    ///
+    /// If there is no message waiting, `BroadcastError::Empty` is returned.
+    pub fn try_recv(&self) -> Result<T, BroadcastError<T>> {
+        match self.queue.pop() {
+            Some(data) => Ok(data),
+            None => self.empty_or_disconnected(BroadcastError::Empty),
+        }
+    }
+
+    /// Receive a message from the Broadcast, blocking for at most `timeout`.
+    ///
+    /// If no message arrives in time, `BroadcastError::Timeout` is returned.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, BroadcastError<T>> {
+        match self.queue.pop_timeout(timeout) {
+            Some(data) => Ok(data),
+            None => self.empty_or_disconnected(BroadcastError::Timeout),
+        }
+    }
+
+    /// Receive a message from the Broadcast, blocking until `deadline` at the
+    /// latest.
+    ///
+    /// If no message arrives in time, `BroadcastError::Timeout` is returned.
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, BroadcastError<T>> {
+        self.recv_timeout(deadline.saturating_duration_since(Instant::now()))
     }
 }
 
-impl<T> Clone for Consumer<T> {
+impl<T: Send> Clone for Consumer<T> {
     fn clone(&self) -> Self {
-        let (s, r) = channel();
-        self.inner.add_sender(s);
-        Consumer {
-            inner: self.inner.clone(),
-            receiver: r,
-        }
+        Consumer::new(self.inner.clone(), self.filter.clone(), self.cap, self.policy)
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        // Also wakes a broadcaster blocked on this consumer's full queue.
+        self.queue.close();
     }
 }
 
 /// Create a (Broadcast<T>, Consumer<T>) pair.
-pub fn broadcast_channel<T: Clone>() -> (Broadcast<T>, Consumer<T>) {
+pub fn broadcast_channel<T: Clone + Send>() -> (Broadcast<T>, Consumer<T>) {
     let broadcast = Broadcast::new();
     let consumer = broadcast.consume();
     (broadcast, consumer)
@@ -136,25 +525,30 @@ pub fn broadcast_channel<T: Clone>() -> (Broadcast<T>, Consumer<T>) {
 
 #[cfg(test)]
 mod test {
-    use broadcast::broadcast_channel;
-    use super::Inner;
+    use broadcast::{broadcast_channel, Broadcast, BroadcastError, OverflowPolicy};
+    use mpmc::MutexLinkedList;
+    use super::{Delivery, Inner, Subscriber};
 
-    use std::sync::{Arc, Mutex};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::sync::Arc;
     use std::sync::mpsc::{channel};
-    use std::thread::spawn;
+    use std::thread::{sleep, spawn};
+    use std::time::{Duration, Instant};
 
     #[test]
     fn inner_iterator() {
-        let (s1, r1) = channel();
-        let (s2, r2) = channel();
-        let inner = Arc::new(Inner { senders: Mutex::new(vec!(s1, s2)) });
+        let q1 = MutexLinkedList::new();
+        let q2 = MutexLinkedList::new();
+        let inner = Arc::new(Inner::new());
+        inner.add_sender(Subscriber::new(q1.clone(), None, OverflowPolicy::Block));
+        inner.add_sender(Subscriber::new(q2.clone(), None, OverflowPolicy::Block));
         let guard = inner.read_senders();
         for s in guard.iter() {
-            assert!(s.send(10u8).is_ok());
+            assert_eq!(s.send(10u8), Delivery::Sent);
         }
 
-        assert_eq!(r1.recv().unwrap(), 10u8);
-        assert_eq!(r2.recv().unwrap(), 10u8);
+        assert_eq!(q1.pop().unwrap(), 10u8);
+        assert_eq!(q2.pop().unwrap(), 10u8);
     }
 
     #[test]
@@ -174,6 +568,238 @@ mod test {
         assert_eq!(res.unwrap(), 9u8)
     }
 
+    #[test]
+    fn skips_dropped_consumers() {
+        let (p, c1) = broadcast_channel();
+        let c2 = c1.clone();
+        let c3 = c1.clone();
+        assert_eq!(p.send(1u8), Ok(3));
+
+        drop(c2);
+        assert_eq!(p.send(2u8), Ok(2));
+        assert_eq!(p.inner.read_senders().len(), 2);
+        assert_eq!(c1.recv().unwrap(), 1);
+        assert_eq!(c1.recv().unwrap(), 2);
+        assert_eq!(c3.recv().unwrap(), 1);
+        assert_eq!(c3.recv().unwrap(), 2);
+
+        drop(c1);
+        drop(c3);
+        assert_eq!(p.send(3u8), Err(BroadcastError::SendError(3)));
+    }
+
+    #[test]
+    fn try_and_timed_recv() {
+        let (p, c) = broadcast_channel();
+        assert_eq!(c.try_recv(), Err(BroadcastError::Empty));
+        assert_eq!(c.recv_timeout(Duration::from_millis(10)), Err(BroadcastError::Timeout));
+        assert_eq!(c.recv_deadline(Instant::now()), Err(BroadcastError::Timeout));
+
+        assert_eq!(p.send(1u8), Ok(1));
+        assert_eq!(p.send(2u8), Ok(1));
+        assert_eq!(c.try_recv(), Ok(1));
+        assert_eq!(c.recv_timeout(Duration::from_millis(10)), Ok(2));
+
+        drop(p);
+        assert_eq!(c.try_recv(), Err(BroadcastError::Disconnected));
+        assert_eq!(c.recv_deadline(Instant::now() + Duration::from_secs(10)),
+                   Err(BroadcastError::Disconnected));
+    }
+
+    #[test]
+    fn replay_to_late_consumers() {
+        let p = Broadcast::with_replay(2);
+        assert_eq!(p.send(1u8), Err(BroadcastError::Kept));
+        assert_eq!(p.send(2), Err(BroadcastError::Kept));
+        assert_eq!(p.send(3), Err(BroadcastError::Kept));
+
+        let c1 = p.consume();
+        assert_eq!(c1.try_recv(), Ok(2));
+        assert_eq!(c1.try_recv(), Ok(3));
+        assert_eq!(c1.try_recv(), Err(BroadcastError::Empty));
+
+        assert_eq!(p.send(4), Ok(1));
+        let c2 = c1.clone();
+        let fours = p.consume_filtered(|v| *v == 4);
+        assert_eq!(c1.try_recv(), Ok(4));
+        assert_eq!(c2.try_recv(), Ok(3));
+        assert_eq!(c2.try_recv(), Ok(4));
+        assert_eq!(fours.try_recv(), Ok(4));
+        assert_eq!(fours.try_recv(), Err(BroadcastError::Empty));
+    }
+
+    #[test]
+    fn close_and_count() {
+        let (p, c1) = broadcast_channel();
+        let p2 = p.clone();
+        let c2 = p.consume_filtered(|_| false);
+        assert_eq!(p.consumer_count(), 2);
+        drop(c2);
+        assert_eq!(p2.consumer_count(), 1);
+
+        assert_eq!(p.send(1u8), Ok(1));
+        assert!(!c1.is_closed());
+        p2.close();
+        assert!(c1.is_closed());
+        assert_eq!(p.consumer_count(), 0);
+        assert_eq!(p.send(2), Err(BroadcastError::SendError(2)));
+
+        assert_eq!(c1.recv(), Ok(1));
+        assert_eq!(c1.recv(), Err(BroadcastError::Disconnected));
+        assert_eq!(c1.clone().try_recv(), Err(BroadcastError::Disconnected));
+    }
+
+    #[test]
+    fn overflow_policies() {
+        let p = Broadcast::new();
+        let newest = p.consume_with(2, OverflowPolicy::DropNewest);
+        let oldest = p.consume_with(2, OverflowPolicy::DropOldest);
+        let disconnect = p.consume_with(2, OverflowPolicy::Disconnect);
+        let unbounded = p.consume();
+
+        assert_eq!(p.send(1u8), Ok(4));
+        assert_eq!(p.send(2), Ok(4));
+        assert_eq!(p.send(3), Ok(2));
+        assert_eq!(p.consumer_count(), 3);
+        assert!(disconnect.is_closed());
+
+        assert_eq!(newest.try_recv(), Ok(1));
+        assert_eq!(newest.try_recv(), Ok(2));
+        assert_eq!(newest.try_recv(), Err(BroadcastError::Empty));
+        assert_eq!(oldest.try_recv(), Ok(2));
+        assert_eq!(oldest.try_recv(), Ok(3));
+        assert_eq!(disconnect.try_recv(), Ok(1));
+        assert_eq!(disconnect.try_recv(), Ok(2));
+        assert_eq!(disconnect.try_recv(), Err(BroadcastError::Disconnected));
+        assert_eq!(unbounded.try_recv(), Ok(1));
+    }
+
+    #[test]
+    fn block_policy() {
+        let p = Broadcast::new();
+        let lossless = p.consume_with(1, OverflowPolicy::Block);
+        assert_eq!(p.send(1u8), Ok(1));
+
+        let receiver = spawn(move || {
+            sleep(Duration::from_millis(10));
+            assert_eq!(lossless.recv(), Ok(1));
+            assert_eq!(lossless.recv(), Ok(2));
+        });
+
+        // Blocks until the receiver makes room.
+        assert_eq!(p.send(2), Ok(1));
+        receiver.join().unwrap();
+    }
+
+    #[test]
+    fn clone_while_blocked() {
+        let p = Broadcast::new();
+        let lossless = p.consume_with(1, OverflowPolicy::Block);
+        assert_eq!(p.send(1u8), Ok(1));
+
+        let receiver = spawn(move || {
+            sleep(Duration::from_millis(10));
+            // The broadcaster is blocked on this consumer, cloning must not
+            // wait for it.
+            let clone = lossless.clone();
+            assert_eq!(lossless.recv(), Ok(1));
+            assert_eq!(lossless.recv(), Ok(2));
+            assert_eq!(clone.try_recv(), Err(BroadcastError::Empty));
+        });
+
+        assert_eq!(p.send(2), Ok(1));
+        receiver.join().unwrap();
+    }
+
+    #[test]
+    fn close_while_blocked() {
+        let p = Broadcast::new();
+        let other = p.consume();
+        let lossless = p.consume_with(1, OverflowPolicy::Block);
+        assert_eq!(p.send(1u8), Ok(2));
+
+        let closer = {
+            let p = p.clone();
+            spawn(move || {
+                sleep(Duration::from_millis(10));
+                p.close();
+            })
+        };
+
+        // Blocks on the full queue until the broadcast is closed. The message
+        // still reached the consumer ahead of it.
+        assert_eq!(p.send(2), Ok(1));
+        closer.join().unwrap();
+        assert_eq!(lossless.recv(), Ok(1));
+        assert_eq!(lossless.recv(), Err(BroadcastError::Disconnected));
+        assert_eq!(other.recv(), Ok(1));
+        assert_eq!(other.recv(), Ok(2));
+        assert_eq!(other.recv(), Err(BroadcastError::Disconnected));
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity must be greater than 0")]
+    fn zero_capacity() {
+        let p = Broadcast::<u8>::new();
+        p.consume_with(0, OverflowPolicy::DropOldest);
+    }
+
+    #[test]
+    fn cloned_broadcasters() {
+        let (p1, c1) = broadcast_channel();
+        let p2 = p1.clone();
+
+        assert_eq!(p1.send(1u8), Ok(1));
+        drop(p1);
+        assert_eq!(p2.send(2), Ok(1));
+        let c2 = c1.clone();
+        drop(p2);
+
+        // Queued messages are still received before the disconnect.
+        assert_eq!(c1.recv(), Ok(1));
+        assert_eq!(c1.recv(), Ok(2));
+        assert_eq!(c1.recv(), Err(BroadcastError::Disconnected));
+        assert_eq!(c2.recv(), Err(BroadcastError::Disconnected));
+        assert_eq!(c2.clone().recv(), Err(BroadcastError::Disconnected));
+    }
+
+    #[test]
+    fn filtered_consumers() {
+        let (p, all) = broadcast_channel();
+        let big = p.consume_filtered(|v: &u8| *v >= 2);
+        let big2 = big.clone();
+
+        assert_eq!(p.send(1), Ok(1));
+        assert_eq!(p.send(2), Ok(3));
+        assert_eq!(all.recv(), Ok(1));
+        assert_eq!(all.recv(), Ok(2));
+        assert_eq!(big.try_recv(), Ok(2));
+        assert_eq!(big.try_recv(), Err(BroadcastError::Empty));
+        assert_eq!(big2.try_recv(), Ok(2));
+
+        drop(all);
+        assert_eq!(p.send(1), Ok(0));
+        assert_eq!(p.send(3), Ok(2));
+
+        // A dropped filtered consumer is forgotten even if it never matches.
+        drop(big);
+        drop(big2);
+        assert_eq!(p.send(1), Err(BroadcastError::SendError(1)));
+    }
+
+    #[test]
+    fn panicking_filter() {
+        let (p, c) = broadcast_channel();
+        let picky = p.consume_filtered(|v: &u8| if *v == 0 { panic!("zero") } else { true });
+
+        let res = catch_unwind(AssertUnwindSafe(|| p.send(0u8)));
+        assert!(res.is_err());
+        assert_eq!(p.send(1), Ok(2));
+        assert_eq!(picky.try_recv(), Ok(1));
+        assert_eq!(c.try_recv(), Ok(0));
+        assert_eq!(c.try_recv(), Ok(1));
+    }
+
     #[test]
     fn test_send_threads() {
         let (p, c1) = broadcast_channel();
@@ -0,0 +1,155 @@
+// A broadcast that wraps each message in an Arc once, then hands every consumer
+// a clone of the Arc. Sending costs a reference count bump per consumer rather
+// than a full clone of the message.
+
+use std::sync::Arc;
+
+use broadcast::{Broadcast, BroadcastError, Consumer, OverflowPolicy};
+
+/// A Consumer of a SharedBroadcast, which receives each message as an Arc.
+pub type SharedConsumer<T> = Consumer<Arc<T>>;
+
+/// Sends each message to every consumer without cloning it.
+///
+/// The message only needs to be `Send + Sync`, not `Clone`. Every consumer
+/// receives an `Arc` pointing at the same message.
+pub struct SharedBroadcast<T> {
+    inner: Broadcast<Arc<T>>,
+}
+
+impl<T: Send + Sync> SharedBroadcast<T> {
+    /// Create a new SharedBroadcast struct.
+    pub fn new() -> SharedBroadcast<T> {
+        SharedBroadcast { inner: Broadcast::new() }
+    }
+
+    /// Create a new SharedBroadcast that keeps the last `n` messages sent for
+    /// consumers that join later.
+    ///
+    /// See `Broadcast::with_replay`.
+    pub fn with_replay(n: usize) -> SharedBroadcast<T> {
+        SharedBroadcast { inner: Broadcast::with_replay(n) }
+    }
+
+    /// Create a SharedConsumer that listens to messages from the broadcaster.
+    pub fn consume(&self) -> SharedConsumer<T> {
+        self.inner.consume()
+    }
+
+    /// Create a SharedConsumer with a bounded queue.
+    ///
+    /// See `Broadcast::consume_with`. Panics if `cap` is 0.
+    pub fn consume_with(&self, cap: usize, policy: OverflowPolicy) -> SharedConsumer<T> {
+        self.inner.consume_with(cap, policy)
+    }
+
+    /// Create a SharedConsumer that only receives the messages for which `pred`
+    /// returns true.
+    ///
+    /// See `Broadcast::consume_filtered`.
+    pub fn consume_filtered<F>(&self, pred: F) -> SharedConsumer<T>
+        where F: Fn(&T) -> bool + Send + Sync + 'static
+    {
+        self.inner.consume_filtered(move |data: &Arc<T>| pred(data))
+    }
+
+    /// Close the broadcast for every handle.
+    ///
+    /// See `Broadcast::close`.
+    pub fn close(&self) {
+        self.inner.close()
+    }
+
+    /// Returns the number of consumers that can still receive new messages.
+    ///
+    /// See `Broadcast::consumer_count`.
+    pub fn consumer_count(&self) -> usize {
+        self.inner.consumer_count()
+    }
+
+    /// Send a message on the broadcast.
+    ///
+    /// See `Broadcast::send`. Returns the number of consumers the message was
+    /// sent to. If there are none, the message is returned in the Err(..). A
+    /// SharedBroadcast created with `with_replay` keeps the message for later
+    /// consumers instead, and returns `BroadcastError::Kept`.
+    pub fn send(&self, data: T) -> Result<usize, BroadcastError<T>> {
+        match self.inner.send(Arc::new(data)) {
+            Ok(n) => Ok(n),
+            Err(BroadcastError::SendError(data)) => match Arc::try_unwrap(data) {
+                Ok(data) => Err(BroadcastError::SendError(data)),
+                // Only the replay history could hold a clone of the Arc.
+                Err(_) => Err(BroadcastError::Kept),
+            },
+            Err(BroadcastError::Empty) => Err(BroadcastError::Empty),
+            Err(BroadcastError::Timeout) => Err(BroadcastError::Timeout),
+            Err(BroadcastError::Disconnected) => Err(BroadcastError::Disconnected),
+            Err(BroadcastError::Lagged(n)) => Err(BroadcastError::Lagged(n)),
+            Err(BroadcastError::Kept) => Err(BroadcastError::Kept),
+        }
+    }
+}
+
+impl<T: Send + Sync> Default for SharedBroadcast<T> {
+    fn default() -> SharedBroadcast<T> {
+        SharedBroadcast::new()
+    }
+}
+
+impl<T> Clone for SharedBroadcast<T> {
+    fn clone(&self) -> SharedBroadcast<T> {
+        SharedBroadcast { inner: self.inner.clone() }
+    }
+}
+
+/// Create a (SharedBroadcast<T>, SharedConsumer<T>) pair.
+pub fn shared_broadcast_channel<T: Send + Sync>() -> (SharedBroadcast<T>, SharedConsumer<T>) {
+    let broadcast = SharedBroadcast::new();
+    let consumer = broadcast.consume();
+    (broadcast, consumer)
+}
+
+#[cfg(test)]
+mod test {
+    use broadcast::{shared_broadcast_channel, BroadcastError, SharedBroadcast};
+    use std::sync::Arc;
+
+    // Deliberately not Clone.
+    #[derive(PartialEq, Debug)]
+    struct Snapshot(Vec<u8>);
+
+    #[test]
+    fn shares_one_message() {
+        let (p, c1) = shared_broadcast_channel();
+        let c2 = c1.clone();
+        let empty = p.consume_filtered(|s: &Snapshot| s.0.is_empty());
+
+        assert_eq!(p.send(Snapshot(vec![1, 2, 3])), Ok(2));
+        let m1 = c1.recv().unwrap();
+        let m2 = c2.recv().unwrap();
+        assert!(Arc::ptr_eq(&m1, &m2));
+        assert_eq!(*m1, Snapshot(vec![1, 2, 3]));
+
+        assert!(empty.try_recv().is_err());
+
+        drop(c1);
+        drop(c2);
+        drop(empty);
+        match p.send(Snapshot(vec![4])) {
+            Err(BroadcastError::SendError(s)) => assert_eq!(s, Snapshot(vec![4])),
+            _ => panic!("send should fail with no consumers"),
+        }
+    }
+
+    #[test]
+    fn replay_without_consumers() {
+        let p = SharedBroadcast::with_replay(1);
+        assert_eq!(p.send(Snapshot(vec![1])), Err(BroadcastError::Kept));
+        assert_eq!(p.send(Snapshot(vec![2])), Err(BroadcastError::Kept));
+
+        let c = p.consume();
+        assert_eq!(*c.try_recv().unwrap(), Snapshot(vec![2]));
+        assert!(c.try_recv().is_err());
+        assert_eq!(p.send(Snapshot(vec![3])), Ok(1));
+    }
+}
@@ -0,0 +1,59 @@
+use std::ops::{Deref, DerefMut};
+
+/// Pads and aligns a value to the length of a cache line.
+///
+/// Two values that share a cache line will contend with each other even when
+/// different threads only touch one of them. Wrapping frequently written fields
+/// such as queue indices in `CachePadded` keeps each on its own line.
+///
+/// The alignment is 128 bytes rather than 64: modern x86 CPUs prefetch cache
+/// lines in adjacent pairs, and some ARM cores have 128-byte lines.
+#[derive(Default, Debug)]
+#[repr(align(128))]
+pub struct CachePadded<T> {
+    value: T,
+}
+
+impl<T> CachePadded<T> {
+    /// Pad and align a value to the length of a cache line.
+    pub fn new(value: T) -> CachePadded<T> {
+        CachePadded { value }
+    }
+
+    /// Returns the inner value.
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for CachePadded<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+    use super::CachePadded;
+
+    #[test]
+    fn test_alignment() {
+        assert_eq!(mem::align_of::<CachePadded<u8>>(), 128);
+        assert_eq!(mem::size_of::<CachePadded<u8>>(), 128);
+
+        let pair = [CachePadded::new(1u8), CachePadded::new(2u8)];
+        let a = &*pair[0] as *const u8 as usize;
+        let b = &*pair[1] as *const u8 as usize;
+        assert_eq!(b - a, 128);
+        assert_eq!(*pair[1], 2);
+    }
+}
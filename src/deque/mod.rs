@@ -0,0 +1,365 @@
+//! A Chase-Lev work-stealing deque.
+//!
+//! A `Worker` owns the deque and pushes and pops values at one end. Any number
+//! of `Stealer`s may take values from the other end. A shared `LockFreeQueue`
+//! can act as the injector that feeds work into each `Worker`.
+//!
+//! This follows "Correct and Efficient Work-Stealing for Weak Memory Models"
+//! by Lê, Pop, Cohen and Zappa Nardelli.
+
+use std::cell::{Cell, UnsafeCell};
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{fence, AtomicIsize, AtomicPtr};
+use std::sync::atomic::Ordering::{Acquire, Release, Relaxed, SeqCst};
+
+use mpmc::LockFreeQueue;
+use CachePadded;
+
+const MIN_CAPACITY: usize = 32;
+
+// The most values a single steal_batch will move.
+const MAX_BATCH: isize = 32;
+
+/// The result of a steal operation.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Steal<T> {
+    /// The deque was empty.
+    Empty,
+    /// A value was stolen.
+    Success(T),
+    /// Lost a race with another thread, the steal should be tried again.
+    Retry,
+}
+
+struct Buffer<T> {
+    slots: Vec<UnsafeCell<MaybeUninit<T>>>,
+    mask: isize,
+}
+
+impl<T> Buffer<T> {
+    fn with_capacity(cap: usize) -> *mut Buffer<T> {
+        Box::into_raw(Box::new(Buffer {
+            slots: (0..cap).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect(),
+            mask: cap as isize - 1,
+        }))
+    }
+
+    fn capacity(&self) -> isize {
+        self.mask + 1
+    }
+
+    // Copy the bits out of slot `i`. The caller decides whether it now owns the
+    // value or must forget the copy.
+    //
+    // A stealer may read a slot while the worker, having wrapped around,
+    // overwrites it. The copy is only used once the stealer's CAS on `top` shows
+    // that did not happen, but the read itself must not be a plain one: it is
+    // volatile and into a MaybeUninit, as in crossbeam-deque.
+    unsafe fn read(&self, i: isize) -> MaybeUninit<T> {
+        ptr::read_volatile(self.slots[(i & self.mask) as usize].get())
+    }
+
+    unsafe fn write(&self, i: isize, value: MaybeUninit<T>) {
+        ptr::write(self.slots[(i & self.mask) as usize].get(), value)
+    }
+}
+
+struct Inner<T> {
+    // Index of the oldest value, where stealers take from.
+    top: CachePadded<AtomicIsize>,
+    // Index one past the newest value, where the worker pushes and pops.
+    bottom: CachePadded<AtomicIsize>,
+    buffer: AtomicPtr<Buffer<T>>,
+    // Buffers replaced by a resize. A stealer may still be reading from one, so
+    // they are kept until the deque itself is dropped.
+    retired: Mutex<Vec<*mut Buffer<T>>>,
+}
+
+unsafe impl<T: Send> Send for Inner<T> {}
+unsafe impl<T: Send> Sync for Inner<T> {}
+
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let top = self.top.load(Relaxed);
+            let bottom = self.bottom.load(Relaxed);
+            let buffer = self.buffer.load(Relaxed);
+            for i in top..bottom {
+                (*buffer).read(i).assume_init();
+            }
+
+            let _: Box<Buffer<T>> = Box::from_raw(buffer);
+            for old in self.retired.lock().unwrap().drain(..) {
+                let _: Box<Buffer<T>> = Box::from_raw(old);
+            }
+        }
+    }
+}
+
+/// The owning end of a work-stealing deque.
+///
+/// Values are pushed and popped in LIFO order. A Worker can be sent to another
+/// thread but not shared between threads.
+pub struct Worker<T: Send> {
+    inner: Arc<Inner<T>>,
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+impl<T: Send> Worker<T> {
+    /// Create a new, empty deque.
+    pub fn new() -> Worker<T> {
+        let inner = Arc::new(Inner {
+            top: CachePadded::new(AtomicIsize::new(0)),
+            bottom: CachePadded::new(AtomicIsize::new(0)),
+            buffer: AtomicPtr::new(Buffer::with_capacity(MIN_CAPACITY)),
+            retired: Mutex::new(Vec::new()),
+        });
+        Worker { inner, _not_sync: PhantomData }
+    }
+
+    /// Create a Stealer that takes values from this deque.
+    pub fn stealer(&self) -> Stealer<T> {
+        Stealer { inner: self.inner.clone() }
+    }
+
+    /// Returns true if the deque holds no values.
+    pub fn is_empty(&self) -> bool {
+        let bottom = self.inner.bottom.load(Relaxed);
+        let top = self.inner.top.load(SeqCst);
+        bottom <= top
+    }
+
+    /// Push a value onto the deque, growing it if needed.
+    pub fn push(&self, value: T) {
+        let bottom = self.inner.bottom.load(Relaxed);
+        let top = self.inner.top.load(Acquire);
+        let mut buffer = self.inner.buffer.load(Relaxed);
+
+        unsafe {
+            if bottom - top >= (*buffer).capacity() {
+                buffer = self.grow(top, bottom, buffer);
+            }
+            (*buffer).write(bottom, MaybeUninit::new(value));
+        }
+
+        fence(Release);
+        self.inner.bottom.store(bottom + 1, Relaxed);
+    }
+
+    /// Pop the most recently pushed value.
+    ///
+    /// If the deque is empty, None is returned.
+    pub fn pop(&self) -> Option<T> {
+        let bottom = self.inner.bottom.load(Relaxed) - 1;
+        let buffer = self.inner.buffer.load(Relaxed);
+        self.inner.bottom.store(bottom, Relaxed);
+        fence(SeqCst);
+        let top = self.inner.top.load(Relaxed);
+
+        if top > bottom {
+            // The deque is empty.
+            self.inner.bottom.store(bottom + 1, Relaxed);
+            return None
+        }
+
+        let value = unsafe { (*buffer).read(bottom) };
+        if top == bottom {
+            // This is the last value, so race the stealers for it.
+            let won = self.inner.top.compare_exchange(top, top + 1, SeqCst, Relaxed).is_ok();
+            self.inner.bottom.store(bottom + 1, Relaxed);
+            if !won {
+                return None
+            }
+        }
+        Some(unsafe { value.assume_init() })
+    }
+
+    /// Move up to `max` values from a shared injector queue into this deque.
+    ///
+    /// Returns the number of values moved.
+    pub fn fill_from(&self, injector: &LockFreeQueue<T>, max: usize) -> usize {
+        let mut moved = 0;
+        while moved < max {
+            match injector.pop() {
+                Some(value) => self.push(value),
+                None => break,
+            }
+            moved += 1;
+        }
+        moved
+    }
+
+    // Copy the live values into a buffer twice the size. The old buffer is
+    // retired rather than freed, since stealers may still be reading it.
+    unsafe fn grow(&self, top: isize, bottom: isize, old: *mut Buffer<T>) -> *mut Buffer<T> {
+        let new = Buffer::with_capacity((*old).capacity() as usize * 2);
+        for i in top..bottom {
+            (*new).write(i, (*old).read(i));
+        }
+        self.inner.buffer.store(new, Release);
+        self.inner.retired.lock().unwrap().push(old);
+        new
+    }
+}
+
+impl<T: Send> Default for Worker<T> {
+    fn default() -> Worker<T> {
+        Worker::new()
+    }
+}
+
+/// The stealing end of a work-stealing deque.
+///
+/// Values are stolen in FIFO order, the oldest value first.
+pub struct Stealer<T: Send> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T: Send> Stealer<T> {
+    /// Returns true if the deque holds no values.
+    pub fn is_empty(&self) -> bool {
+        let top = self.inner.top.load(Acquire);
+        let bottom = self.inner.bottom.load(Acquire);
+        bottom <= top
+    }
+
+    /// Steal the oldest value from the deque.
+    pub fn steal(&self) -> Steal<T> {
+        let top = self.inner.top.load(Acquire);
+        fence(SeqCst);
+        let bottom = self.inner.bottom.load(Acquire);
+        if top >= bottom {
+            return Steal::Empty
+        }
+
+        let buffer = self.inner.buffer.load(Acquire);
+        let value = unsafe { (*buffer).read(top) };
+        if self.inner.top.compare_exchange(top, top + 1, SeqCst, Relaxed).is_err() {
+            // Someone else took it, our copy of the value is not ours to drop.
+            return Steal::Retry
+        }
+        Steal::Success(unsafe { value.assume_init() })
+    }
+
+    /// Steal about half of the values in the deque and push them onto `dest`.
+    ///
+    /// Returns the number of values moved.
+    pub fn steal_batch(&self, dest: &Worker<T>) -> Steal<usize> {
+        let top = self.inner.top.load(Acquire);
+        let bottom = self.inner.bottom.load(Acquire);
+        let batch = ((bottom - top + 1) / 2).min(MAX_BATCH);
+
+        let mut moved = 0;
+        while moved < batch {
+            match self.steal() {
+                Steal::Success(value) => dest.push(value),
+                Steal::Empty => break,
+                Steal::Retry if moved == 0 => return Steal::Retry,
+                Steal::Retry => break,
+            }
+            moved += 1;
+        }
+
+        if moved == 0 {
+            Steal::Empty
+        } else {
+            Steal::Success(moved as usize)
+        }
+    }
+}
+
+impl<T: Send> Clone for Stealer<T> {
+    fn clone(&self) -> Stealer<T> {
+        Stealer { inner: self.inner.clone() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use mpmc::LockFreeQueue;
+    use super::{Steal, Worker};
+
+    #[test]
+    fn test_push_pop_steal() {
+        let w = Worker::new();
+        let s = w.stealer();
+        assert_eq!(w.pop(), None::<u8>);
+        assert_eq!(s.steal(), Steal::Empty);
+
+        // Push past the initial capacity to force the buffer to grow.
+        for i in 0..100 {
+            w.push(i);
+        }
+        assert_eq!(w.pop(), Some(99));
+        assert_eq!(s.steal(), Steal::Success(0));
+        assert_eq!(s.steal(), Steal::Success(1));
+
+        let w2 = Worker::new();
+        assert_eq!(s.steal_batch(&w2), Steal::Success(32));
+        assert_eq!(w2.pop(), Some(33));
+        assert_eq!(s.steal(), Steal::Success(34));
+    }
+
+    #[test]
+    fn test_fill_from_injector() {
+        let injector = LockFreeQueue::with_capacity(8);
+        for i in 0..5u8 {
+            assert!(injector.push(i).is_ok());
+        }
+
+        let w = Worker::new();
+        assert_eq!(w.fill_from(&injector, 3), 3);
+        assert_eq!(w.pop(), Some(2));
+        assert_eq!(w.fill_from(&injector, 10), 2);
+        assert_eq!(w.pop(), Some(4));
+    }
+
+    #[test]
+    fn test_concurrent_steal() {
+        let nmsgs = 10000;
+        let nthieves = 4;
+        let w = Worker::new();
+        let taken = Arc::new(AtomicUsize::new(0));
+
+        let thieves = (0..nthieves).map(|_| {
+            let s = w.stealer();
+            let taken = taken.clone();
+            thread::spawn(move || {
+                let mut sum = 0;
+                while taken.load(Ordering::SeqCst) < nmsgs {
+                    if let Steal::Success(v) = s.steal() {
+                        sum += v;
+                        taken.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+                sum
+            })
+        }).collect::<Vec<_>>();
+
+        let mut sum = 0;
+        for i in 0..nmsgs {
+            w.push(i);
+            if i % 3 == 0 {
+                if let Some(v) = w.pop() {
+                    sum += v;
+                    taken.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        }
+        while let Some(v) = w.pop() {
+            sum += v;
+            taken.fetch_add(1, Ordering::SeqCst);
+        }
+
+        for thr in thieves.into_iter() {
+            sum += thr.join().unwrap();
+        }
+        assert_eq!(sum, nmsgs * (nmsgs - 1) / 2);
+    }
+}
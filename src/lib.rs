@@ -4,5 +4,16 @@
 //!
 //! A library for communication primitives.
 
+pub use cache_padded::CachePadded;
+
+mod cache_padded;
+
 pub mod broadcast;
+pub mod deque;
 pub mod mpmc;
+pub mod mpsc;
+pub mod pubsub;
+pub mod spsc;
+pub mod stack;
+pub mod wait;
+pub mod watch;
@@ -12,19 +12,26 @@
 // channel. See copyright above. I have removed parts unnecessary to my uses and added those that
 // I needed.
 
-use mpmc::{LockFreeQueue};
-
-use std::isize;
-use std::thread;
+use mpmc::{BoundedQueue, ConcurrentQueue};
+use wait::WaitStrategy;
+use CachePadded;
 
+use std::marker::PhantomData;
+use std::sync::{RwLock, RwLockReadGuard};
 use std::sync::atomic::{Ordering, AtomicIsize};
 
 const DISCONNECTED: isize = isize::MIN;
 const FUDGE: isize = 1024;
 
-pub struct Canal<T> {
-    queue: LockFreeQueue<T>,
-    cnt: AtomicIsize, // How many items are on this channel
+pub struct Canal<T, Q> {
+    // The queue is only ever written to when the channel is resized, every
+    // other operation goes through a read lock and the queue's own thread-safe
+    // push/pop. The read lock costs every send and recv an atomic increment and
+    // decrement on the lock's state, whether or not the channel is resized.
+    queue: RwLock<Q>,
+    // How many items are on this channel. Touched by every send and recv, so it
+    // gets a cache line to itself.
+    cnt: CachePadded<AtomicIsize>,
 
     // The number of channels which are currently using this packet.
     channels: AtomicIsize,
@@ -32,6 +39,12 @@ pub struct Canal<T> {
     ports: AtomicIsize,
 
     sender_drain: AtomicIsize,
+
+    // [@chrino]
+    // How receivers wait for data.
+    wait: Box<dyn WaitStrategy>,
+
+    _marker: PhantomData<T>,
 }
 
 /// Failure modes for receiving on the port.
@@ -43,19 +56,57 @@ pub enum Failure {
     Disconnected,
 }
 
-impl<T> Canal<T> {
-   pub fn new(cap: usize) -> Canal<T> {
+impl<T, Q> Canal<T, Q> {
+   pub fn new(queue: Q, wait: Box<dyn WaitStrategy>) -> Canal<T, Q> {
         Canal {
-            queue: LockFreeQueue::with_capacity(cap),
-            cnt: AtomicIsize::new(0),
+            queue: RwLock::new(queue),
+            cnt: CachePadded::new(AtomicIsize::new(0)),
             channels: AtomicIsize::new(1),
             ports: AtomicIsize::new(1),
             sender_drain: AtomicIsize::new(0),
+            wait,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Send, Q: BoundedQueue<T>> Canal<T, Q> {
+
+    // [@chrino]
+    // Swap in a queue of the new capacity, moving all queued items over in
+    // order. Holding the write lock guarantees no sender or receiver is
+    // touching the old queue while it is drained.
+    pub fn set_capacity(&self, cap: usize) -> Result<(), usize> {
+        let mut queue = self.queue.write().unwrap();
+        let resized = Q::with_capacity(cap);
+
+        let len = queue.len();
+        match resized.capacity() {
+            Some(cap) if len > cap => return Err(len),
+            _ => {}
+        }
+
+        while let Some(t) = queue.pop() {
+            assert!(resized.push(t).is_ok(), "resized queue could not hold queued item");
         }
+        *queue = resized;
+        drop(queue);
+
+        // Senders waiting in send_wait may now have room.
+        self.wait.notify();
+        Ok(())
     }
 }
 
-impl<T: Send> Canal<T> {
+impl<T: Send, Q: ConcurrentQueue<T>> Canal<T, Q> {
+    fn queue(&self) -> RwLockReadGuard<'_, Q> {
+        self.queue.read().unwrap()
+    }
+
+    pub fn capacity(&self) -> Option<usize> {
+        self.queue().capacity()
+    }
+
     pub fn send(&self, t: T) -> Result<(), T> {
         // See Port::drop for what's going on
         if self.ports.load(Ordering::SeqCst) == 0 { return Err(t) }
@@ -89,7 +140,7 @@ impl<T: Send> Canal<T> {
             return Err(t)
         }
 
-        try!(self.queue.push(t));
+        self.queue().push(t)?;
         match self.cnt.fetch_add(1, Ordering::SeqCst) {
             // In this case, we have possibly failed to send our data, and
             // we need to consider re-popping the data in order to fully
@@ -109,12 +160,7 @@ impl<T: Send> Canal<T> {
                     loop {
                         // drain the queue, for info on the thread yield see the
                         // discussion in try_recv
-                        loop {
-                            match self.queue.pop() {
-                                Some(..) => {}
-                                None => break,
-                            }
-                        }
+                        while self.queue().pop().is_some() {}
                         // maybe we're done, if we're not the last ones
                         // here, then we need to go try again.
                         if self.sender_drain.fetch_sub(1, Ordering::SeqCst) == 1 {
@@ -132,9 +178,46 @@ impl<T: Send> Canal<T> {
             n => { assert!(n >= 0) }
         }
 
+        self.wait.notify();
         Ok(())
     }
 
+    // [@chrino]
+    // Send, waiting with the channel's WaitStrategy while the queue is full.
+    // Only fails once every port has been dropped.
+    pub fn send_wait(&self, t: T) -> Result<(), T> {
+        let mut t = t;
+        let mut attempt = 0;
+        loop {
+            match self.send(t) {
+                Ok(()) => return Ok(()),
+                Err(v) => t = v,
+            }
+            if self.is_disconnected() {
+                return Err(t)
+            }
+
+            self.wait.wait(attempt, &|| self.has_room() || self.is_disconnected());
+            attempt += 1;
+        }
+    }
+
+    // Whether a send has no chance of ever being received.
+    fn is_disconnected(&self) -> bool {
+        self.ports.load(Ordering::SeqCst) == 0 ||
+            self.cnt.load(Ordering::SeqCst) < DISCONNECTED + FUDGE
+    }
+
+    // Whether the queue may have room for another item. `cnt` is incremented
+    // after an item is pushed, so it can briefly undercount the queue. That is
+    // harmless: the send this wakes just fails to push and waits again.
+    fn has_room(&self) -> bool {
+        match self.capacity() {
+            Some(cap) => self.cnt.load(Ordering::SeqCst) < cap as isize,
+            None => true,
+        }
+    }
+
     // Essentially the exact same thing as the stream decrement function.
     // Returns true if blocking should proceed.
     fn decrement(&self) {
@@ -147,20 +230,26 @@ impl<T: Send> Canal<T> {
     }
 
     pub fn recv(&mut self) -> Result<T, Failure> {
+        let mut attempt = 0;
         loop {
             match self.try_recv() {
                 Err(Failure::Empty) => {}
                 data => { return data },
             }
 
-            thread::yield_now();
+            // `cnt` is non-zero while there is data, and once disconnected.
+            self.wait.wait(attempt, &|| self.cnt.load(Ordering::SeqCst) != 0);
+            attempt += 1;
         }
     }
 
     pub fn try_recv(&self) -> Result<T, Failure> {
-        match self.queue.pop() {
+        let popped = self.queue().pop();
+        match popped {
             Some(data) => {
                 self.decrement();
+                // Senders waiting in send_wait may now have room.
+                self.wait.notify();
                 Ok(data)
             }
 
@@ -170,7 +259,7 @@ impl<T: Send> Canal<T> {
                 match self.cnt.load(Ordering::SeqCst) {
                     n if n != DISCONNECTED => Err(Failure::Empty),
                     _ => {
-                        match self.queue.pop() {
+                        match self.queue().pop() {
                             Some(t) => Ok(t), // Do not decrement b/c we are DISCONNECTED
                             None => Err(Failure::Disconnected),
                         }
@@ -207,6 +296,9 @@ impl<T: Send> Canal<T> {
             DISCONNECTED => {}
             n => { assert!(n >= 0); }
         }
+
+        // Wake up receivers so they see the disconnect.
+        self.wait.notify();
     }
 
     // See the long discussion inside of stream.rs for why the queue is drained,
@@ -224,22 +316,25 @@ impl<T: Send> Canal<T> {
         // TODO: Make sure this is ok in the multiple port case
         let mut steals = 0;
         while {
-            let cnt = self.cnt.compare_and_swap(steals, DISCONNECTED, Ordering::SeqCst);
+            let cnt = match self.cnt.compare_exchange(steals, DISCONNECTED,
+                                                      Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(cnt) | Err(cnt) => cnt,
+            };
             cnt != DISCONNECTED && cnt != steals
         } {
             // See the discussion in 'try_recv' for why we yield
             // control of this thread.
-            loop {
-                match self.queue.pop() {
-                    Some(..) => { steals += 1; }
-                    None => break,
-                }
+            while self.queue().pop().is_some() {
+                steals += 1;
             }
         }
+
+        // Wake senders waiting for room so they see the disconnect.
+        self.wait.notify();
     }
 }
 
-impl<T> Drop for Canal<T> {
+impl<T, Q> Drop for Canal<T, Q> {
     fn drop(&mut self) {
         assert_eq!(self.cnt.load(Ordering::SeqCst), DISCONNECTED);
         assert_eq!(self.channels.load(Ordering::SeqCst), 0);
@@ -250,13 +345,15 @@ impl<T> Drop for Canal<T> {
 #[cfg(test)]
 mod tests {
     use super::Canal;
-    use mpmc::mpmc_channel;
+    use mpmc::{mpmc_channel, Builder, LockFreeQueue};
+    use wait::{Park, Yield};
     use std::thread;
-    use std::sync::{Arc, Barrier};
+    use std::sync::{mpsc, Arc, Barrier};
+    use std::time::Duration;
 
     #[test]
     fn test_send_recv() {
-        let mut canal = Canal::new(20);
+        let mut canal = Canal::new(LockFreeQueue::with_capacity(20), Box::new(Yield));
 
         for i in 0..20 {
             assert!(canal.send(i as u8).is_ok());
@@ -279,10 +376,10 @@ mod tests {
 
     #[test]
     fn test_send_full() {
-        let canal = Canal::new(2);
-        assert!(canal.send(1 as u8).is_ok());
-        assert!(canal.send(2 as u8).is_ok());
-        assert!(canal.send(3 as u8).is_err());
+        let canal = Canal::new(LockFreeQueue::with_capacity(2), Box::new(Yield));
+        assert!(canal.send(1u8).is_ok());
+        assert!(canal.send(2u8).is_ok());
+        assert!(canal.send(3u8).is_err());
         canal.drop_port();
         canal.drop_chan();
     }
@@ -320,6 +417,61 @@ mod tests {
             for thr in recv_vec.into_iter() {
                 thr.join().expect("recv thread errored");
             }
-        }).join().expect("send thread errored");;
+        }).join().expect("send thread errored");
+    }
+
+    #[test]
+    fn test_park_wakes_on_send_and_disconnect() {
+        // A long timeout, so the receiver only wakes in time if it is notified.
+        let (sn, rc) = Builder::new(4)
+            .wait_strategy(Park::new(Duration::from_secs(60)))
+            .build();
+        let (done_tx, done_rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            assert_eq!(rc.recv().unwrap(), 1u8);
+            done_tx.send(()).unwrap();
+            assert!(rc.recv().is_err());
+            done_tx.send(()).unwrap();
+        });
+
+        thread::sleep(Duration::from_millis(10));
+        sn.send(1u8).unwrap();
+        assert!(done_rx.recv_timeout(Duration::from_secs(5)).is_ok(), "recv missed the send");
+        thread::sleep(Duration::from_millis(10));
+        drop(sn);
+        assert!(done_rx.recv_timeout(Duration::from_secs(5)).is_ok(), "recv missed the disconnect");
+    }
+
+    #[test]
+    fn test_send_wait() {
+        let (sn, rc) = Builder::new(2)
+            .wait_strategy(Park::new(Duration::from_secs(60)))
+            .build();
+        let (done_tx, done_rx) = mpsc::channel();
+        assert!(sn.send(1u8).is_ok());
+        assert!(sn.send(2u8).is_ok());
+
+        let sender = {
+            let sn = sn.clone();
+            thread::spawn(move || {
+                done_tx.send(sn.send_wait(3)).unwrap();
+                done_tx.send(sn.send_wait(4)).unwrap();
+                done_tx.send(sn.send_wait(5)).unwrap();
+            })
+        };
+
+        thread::sleep(Duration::from_millis(10));
+        assert!(done_rx.try_recv().is_err());
+        assert_eq!(rc.recv().unwrap(), 1);
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(5)), Ok(Ok(())));
+        assert_eq!(rc.recv().unwrap(), 2);
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(5)), Ok(Ok(())));
+
+        // The queue is full again, dropping the receiver fails the last send.
+        thread::sleep(Duration::from_millis(10));
+        drop(rc);
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(5)), Ok(Err(5)));
+        sender.join().unwrap();
     }
 }
@@ -0,0 +1,39 @@
+/// A queue that is safe to push and pop from multiple threads.
+///
+/// The mpmc channel can be built over any ConcurrentQueue, see
+/// `Builder::build_with`.
+pub trait ConcurrentQueue<T>: Send + Sync {
+    /// Push a value onto the queue.
+    ///
+    /// If the queue is full, the value is returned in the Err(..).
+    fn push(&self, value: T) -> Result<(), T>;
+
+    /// Pop a value off the queue.
+    ///
+    /// If the queue is empty, None is returned.
+    fn pop(&self) -> Option<T>;
+
+    /// Returns the number of values the queue can hold, or None if the queue is
+    /// unbounded.
+    fn capacity(&self) -> Option<usize>;
+
+    /// Returns the number of values in the queue.
+    ///
+    /// When other threads are pushing or popping concurrently this is only an
+    /// approximation.
+    fn len(&self) -> usize;
+
+    /// Returns true if the queue holds no values.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A ConcurrentQueue that is created with a fixed capacity.
+///
+/// Channels built over a BoundedQueue can be resized with
+/// `Sender::set_capacity`.
+pub trait BoundedQueue<T>: ConcurrentQueue<T> + Sized {
+    /// Create an empty queue that holds at least `cap` values.
+    fn with_capacity(cap: usize) -> Self;
+}
@@ -1,25 +1,31 @@
 //! A multi-producer, multi-consumer channel implementation.
 
+mod concurrent_queue;
 mod mutex_linked_list;
 mod mpmc_bounded_queue;
 mod channel;
 
-pub use self::mutex_linked_list::MutexLinkedList;
-pub use self::mpmc_bounded_queue::LockFreeQueue;
+pub use self::concurrent_queue::{ConcurrentQueue, BoundedQueue};
+pub use self::mutex_linked_list::{MutexLinkedList, ListDrain};
+pub use self::mpmc_bounded_queue::{LockFreeQueue, Drain};
 pub use self::channel::Failure;
 
 use std::sync::{Arc};
 use std::cell::UnsafeCell;
 use self::channel::{Canal};
+use wait::{WaitStrategy, Yield};
 
 /// The sending-half of the mpmc channel.
-pub struct Sender<T: Send> {
-    inner: Arc<UnsafeCell<Canal<T>>>,
+///
+/// By default the channel is backed by a LockFreeQueue, any ConcurrentQueue can
+/// be used instead with `Builder::build_with`.
+pub struct Sender<T: Send, Q: ConcurrentQueue<T> = LockFreeQueue<T>> {
+    inner: Arc<UnsafeCell<Canal<T, Q>>>,
 }
 
-unsafe impl<T: Send> Send for Sender<T> {}
+unsafe impl<T: Send, Q: ConcurrentQueue<T>> Send for Sender<T, Q> {}
 
-impl<T: Send> Sender<T> {
+impl<T: Send, Q: ConcurrentQueue<T>> Sender<T, Q> {
     /// Sends data to the channel.
     ///
     /// This method will never block, but may return an error with the value
@@ -29,10 +35,48 @@ impl<T: Send> Sender<T> {
             (*self.inner.get()).send(value)
         }
     }
+
+    /// Sends data to the channel, blocking while it is full.
+    ///
+    /// How it waits is set by the channel's `WaitStrategy`, see
+    /// `Builder::wait_strategy`. If every Receiver has been dropped, the value
+    /// is returned in the Err(..).
+    pub fn send_wait(&self, value: T) -> Result<(), T> {
+        unsafe {
+            (*self.inner.get()).send_wait(value)
+        }
+    }
+
+    /// Returns the number of items the channel can hold, or None if the
+    /// channel is unbounded.
+    pub fn capacity(&self) -> Option<usize> {
+        unsafe {
+            (*self.inner.get()).capacity()
+        }
+    }
 }
 
-impl<T: Send> Clone for Sender<T> {
-    fn clone(&self) -> Sender<T> {
+impl<T: Send, Q: BoundedQueue<T>> Sender<T, Q> {
+    /// Change the number of items the channel can hold.
+    ///
+    /// Items already in the channel are kept, in order. The queue may round the
+    /// capacity up, LockFreeQueue uses the next power of 2. If more items are queued than
+    /// would fit in the new capacity, the channel is left unchanged and the number
+    /// of queued items is returned in the Err(..).
+    ///
+    /// Sends and receives on other threads wait for the resize to finish. To
+    /// allow this, every send and receive takes a read lock on the queue, even
+    /// on channels that are never resized. The lock is uncontended outside of a
+    /// resize, but it is one more atomic operation on the hot path.
+    pub fn set_capacity(&self, cap: usize) -> Result<(), usize> {
+        unsafe {
+            (*self.inner.get()).set_capacity(cap)
+        }
+    }
+}
+
+impl<T: Send, Q: ConcurrentQueue<T>> Clone for Sender<T, Q> {
+    fn clone(&self) -> Sender<T, Q> {
         unsafe {
             (*self.inner.get()).clone_chan();
         }
@@ -41,7 +85,7 @@ impl<T: Send> Clone for Sender<T> {
     }
 }
 
-impl<T: Send> Drop for Sender<T> {
+impl<T: Send, Q: ConcurrentQueue<T>> Drop for Sender<T, Q> {
     fn drop(&mut self) {
         unsafe {
             (*self.inner.get()).drop_chan();
@@ -50,17 +94,18 @@ impl<T: Send> Drop for Sender<T> {
 }
 
 /// The receiving-half of the mpmc channel.
-pub struct Receiver<T: Send> {
-    inner: Arc<UnsafeCell<Canal<T>>>,
+pub struct Receiver<T: Send, Q: ConcurrentQueue<T> = LockFreeQueue<T>> {
+    inner: Arc<UnsafeCell<Canal<T, Q>>>,
 }
 
-unsafe impl<T: Send> Send for Receiver<T> {}
+unsafe impl<T: Send, Q: ConcurrentQueue<T>> Send for Receiver<T, Q> {}
 
-impl<T: Send> Receiver<T> {
+impl<T: Send, Q: ConcurrentQueue<T>> Receiver<T, Q> {
     /// Receive data from the channel.
     ///
     /// This method will block until either new data is sent or all senders have
-    /// disconnected.
+    /// disconnected. How it waits is set by the channel's `WaitStrategy`, see
+    /// `Builder::wait_strategy`.
     pub fn recv(&self) -> Result<T, Failure> {
         unsafe {
             (*self.inner.get()).recv()
@@ -68,8 +113,8 @@ impl<T: Send> Receiver<T> {
     }
 }
 
-impl<T: Send> Clone for Receiver<T> {
-    fn clone(&self) -> Receiver<T> {
+impl<T: Send, Q: ConcurrentQueue<T>> Clone for Receiver<T, Q> {
+    fn clone(&self) -> Receiver<T, Q> {
         unsafe {
             (*self.inner.get()).clone_port();
         }
@@ -77,7 +122,7 @@ impl<T: Send> Clone for Receiver<T> {
     }
 }
 
-impl<T: Send> Drop for Receiver<T> {
+impl<T: Send, Q: ConcurrentQueue<T>> Drop for Receiver<T, Q> {
     fn drop(&mut self) {
         unsafe {
             (*self.inner.get()).drop_port();
@@ -85,21 +130,58 @@ impl<T: Send> Drop for Receiver<T> {
     }
 }
 
+/// Builds an mpmc channel with non-default settings.
+pub struct Builder {
+    cap: usize,
+    wait: Box<dyn WaitStrategy>,
+}
+
+impl Builder {
+    /// Start building a channel with the specified capacity.
+    pub fn new(cap: usize) -> Builder {
+        Builder { cap, wait: Box::new(Yield) }
+    }
+
+    /// Set how `Receiver::recv` waits while the channel is empty, and how
+    /// `Sender::send_wait` waits while it is full.
+    ///
+    /// The default is `wait::Yield`.
+    pub fn wait_strategy<W: WaitStrategy + 'static>(mut self, wait: W) -> Builder {
+        self.wait = Box::new(wait);
+        self
+    }
+
+    /// Create the channel pair over a LockFreeQueue.
+    pub fn build<T: Send>(self) -> (Sender<T>, Receiver<T>) {
+        let queue = LockFreeQueue::with_capacity(self.cap);
+        self.build_with(queue)
+    }
+
+    /// Create the channel pair over the given queue.
+    ///
+    /// The capacity passed to `Builder::new` is not used, the queue's own
+    /// capacity applies. For example, `MutexLinkedList::new()` gives an unbounded
+    /// channel.
+    pub fn build_with<T: Send, Q: ConcurrentQueue<T>>(self, queue: Q) -> (Sender<T, Q>, Receiver<T, Q>) {
+        let inner = Arc::new(UnsafeCell::new(Canal::new(queue, self.wait)));
+        let sn = Sender { inner: inner.clone() };
+        let rc = Receiver { inner };
+        (sn, rc)
+    }
+}
+
 /// Create a channel pair using a lock-free queue with specified capacity.
 ///
 /// Note: This is not ready for use in production, some bugs are still
 /// being actively worked out.
 pub fn mpmc_channel<T: Send>(cap: usize) -> (Sender<T>, Receiver<T>) {
-    let inner = Arc::new(UnsafeCell::new(Canal::new(cap)));
-    let sn = Sender { inner: inner.clone() };
-    let rc = Receiver { inner: inner };
-    (sn, rc)
+    Builder::new(cap).build()
 }
 
 #[cfg(test)]
 mod tests {
     use std::thread;
-    use mpmc::{mpmc_channel};
+    use mpmc::{mpmc_channel, Builder, MutexLinkedList};
 
     #[test]
     fn test_producer_consumer() {
@@ -132,4 +214,44 @@ mod tests {
             x.join().unwrap();
         }
     }
+
+    #[test]
+    fn test_set_capacity() {
+        let (sn, rc) = mpmc_channel(2);
+        assert_eq!(sn.capacity(), Some(2));
+        assert!(sn.send(1u8).is_ok());
+        assert!(sn.send(2u8).is_ok());
+        assert!(sn.send(3u8).is_err());
+
+        assert!(sn.set_capacity(4).is_ok());
+        assert_eq!(sn.capacity(), Some(4));
+        assert!(sn.send(3u8).is_ok());
+        assert!(sn.send(4u8).is_ok());
+
+        assert_eq!(sn.set_capacity(2), Err(4));
+        assert_eq!(sn.capacity(), Some(4));
+
+        for i in 1..5 {
+            assert_eq!(rc.recv().unwrap(), i);
+        }
+
+        assert!(sn.set_capacity(2).is_ok());
+        assert_eq!(sn.capacity(), Some(2));
+    }
+
+    #[test]
+    fn test_linked_list_channel() {
+        let (sn, rc) = Builder::new(0).build_with(MutexLinkedList::new());
+        assert_eq!(sn.capacity(), None);
+
+        for i in 0..100u8 {
+            assert!(sn.send(i).is_ok());
+        }
+        for i in 0..100u8 {
+            assert_eq!(rc.recv().unwrap(), i);
+        }
+
+        drop(sn);
+        assert!(rc.recv().is_err());
+    }
 }
@@ -31,12 +31,19 @@
 // And some changes from https://github.com/carllerche/mio
 
 use std::cell::UnsafeCell;
+use std::thread;
+
+use CachePadded;
+use mpmc::{BoundedQueue, ConcurrentQueue};
 
 use std::sync::atomic::AtomicUsize;
-use std::sync::atomic::Ordering::{Relaxed, Release, Acquire};
+use std::sync::atomic::Ordering::{Relaxed, Release, Acquire, SeqCst};
 
 struct Node<T> {
     sequence: AtomicUsize,
+    // The number of `peek_with` calls looking at this node's value. A consumer
+    // that claims the node waits for this to drop to 0 before taking the value.
+    peekers: AtomicUsize,
     value: Option<T>,
 }
 
@@ -48,24 +55,15 @@ unsafe impl<T: Sync> Sync for Node<T> {}
 /// This queue is implemented as a bounded ring buffer and thus must be initialized with
 /// a size at creation.
 pub struct LockFreeQueue<T> {
-    #[allow(dead_code)]
-    pad0: [u8; 64],
-
     buffer: Vec<UnsafeCell<Node<T>>>,
     mask: usize,
 
-    #[allow(dead_code)]
-    pad1: [u8; 64],
-
-    enqueue_pos: AtomicUsize,
-
-    #[allow(dead_code)]
-    pad2: [u8; 64],
-
-    dequeue_pos: AtomicUsize,
-
-    #[allow(dead_code)]
-    pad3: [u8; 64],
+    // Producers and consumers each hammer their own index, keep them off the
+    // cache line holding the read-only buffer and mask, and off each other's.
+    // The slots themselves are not padded, that would cost a cache line per
+    // value for little gain since neighbouring slots are rarely contended.
+    enqueue_pos: CachePadded<AtomicUsize>,
+    dequeue_pos: CachePadded<AtomicUsize>,
 }
 
 unsafe impl<T: Send> Send for LockFreeQueue<T> {}
@@ -87,20 +85,39 @@ impl<T> LockFreeQueue<T> {
             capacity
         };
         let buffer = (0..capacity).map(|i| {
-            UnsafeCell::new(Node { sequence:AtomicUsize::new(i), value: None })
+            UnsafeCell::new(Node { sequence:AtomicUsize::new(i), peekers: AtomicUsize::new(0), value: None })
         }).collect::<Vec<_>>();
 
         LockFreeQueue{
-            pad0: [0; 64],
-            buffer: buffer,
+            buffer,
             mask: capacity-1,
-            pad1: [0; 64],
-            enqueue_pos: AtomicUsize::new(0),
-            pad2: [0; 64],
-            dequeue_pos: AtomicUsize::new(0),
-            pad3: [0; 64],
+            enqueue_pos: CachePadded::new(AtomicUsize::new(0)),
+            dequeue_pos: CachePadded::new(AtomicUsize::new(0)),
         }
     }
+
+    /// Returns the number of values the queue can hold.
+    ///
+    /// This may be larger than the capacity requested in `with_capacity`, as the
+    /// queue is always sized to a power of 2.
+    pub fn capacity(&self) -> usize {
+        self.mask + 1
+    }
+
+    /// Returns the number of values currently in the queue.
+    ///
+    /// When other threads are pushing or popping concurrently this is only an
+    /// approximation.
+    pub fn len(&self) -> usize {
+        let dequeue_pos = self.dequeue_pos.load(Acquire);
+        let enqueue_pos = self.enqueue_pos.load(Acquire);
+        enqueue_pos.saturating_sub(dequeue_pos)
+    }
+
+    /// Returns true if the queue currently holds no values.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 impl<T: Send> LockFreeQueue<T> {
@@ -116,7 +133,9 @@ impl<T: Send> LockFreeQueue<T> {
             let diff: isize = seq as isize - pos as isize;
 
             if diff == 0 {
-                let enqueue_pos = self.enqueue_pos.compare_and_swap(pos, pos+1, Relaxed);
+                let enqueue_pos = match self.enqueue_pos.compare_exchange(pos, pos+1, Relaxed, Relaxed) {
+                    Ok(p) | Err(p) => p,
+                };
                 if enqueue_pos == pos {
                     unsafe {
                         (*node.get()).value = Some(value);
@@ -147,9 +166,16 @@ impl<T: Send> LockFreeQueue<T> {
             let seq = unsafe { (*node.get()).sequence.load(Acquire) };
             let diff: isize = seq as isize - (pos + 1) as isize;
             if diff == 0 {
-                let dequeue_pos = self.dequeue_pos.compare_and_swap(pos, pos+1, Relaxed);
+                // SeqCst pairs with peek_with: either it sees this claim and
+                // backs off, or this sees its peek and waits for it to finish.
+                let dequeue_pos = match self.dequeue_pos.compare_exchange(pos, pos+1, SeqCst, Relaxed) {
+                    Ok(p) | Err(p) => p,
+                };
                 if dequeue_pos == pos {
                     unsafe {
+                        while (*node.get()).peekers.load(SeqCst) != 0 {
+                            thread::yield_now();
+                        }
                         let value = (*node.get()).value.take();
                         (*node.get()).sequence.store(pos + mask + 1, Release);
                         return value
@@ -165,6 +191,112 @@ impl<T: Send> LockFreeQueue<T> {
             }
         }
     }
+
+    /// Call `f` with a reference to the value at the head of the queue, without
+    /// removing it.
+    ///
+    /// If the queue is empty, None is returned. This is best-effort: another
+    /// consumer may pop the value as soon as `f` returns. A consumer that pops
+    /// the peeked value waits for `f` to return, so it should be kept short.
+    /// Pops of other values and pushes are not held up.
+    ///
+    /// `f` must not pop from this queue, directly or through `drain` or
+    /// `clear`: the pop would wait for `f` to return and never finish. Peeking
+    /// again from `f` is fine.
+    ///
+    /// Several threads may peek the same value at once, so `T` must be `Sync`.
+    pub fn peek_with<F, R>(&self, f: F) -> Option<R>
+        where T: Sync, F: FnOnce(&T) -> R
+    {
+        let mask = self.mask;
+        let mut pos = self.dequeue_pos.load(Relaxed);
+        loop {
+            let node = &self.buffer[pos & mask];
+            let seq = unsafe { (*node.get()).sequence.load(Acquire) };
+            let diff: isize = seq as isize - (pos + 1) as isize;
+            if diff == 0 {
+                // Mark the node as peeked, then check that no consumer claimed
+                // it in the meantime. A consumer that claims it from now on
+                // waits for the guard to drop.
+                let peekers = unsafe { &(*node.get()).peekers };
+                peekers.fetch_add(1, SeqCst);
+                let _release = PeekGuard { peekers };
+                let dequeue_pos = self.dequeue_pos.load(SeqCst);
+                if dequeue_pos == pos {
+                    let value = unsafe { (*node.get()).value.as_ref() };
+                    return value.map(f)
+                }
+                pos = dequeue_pos;
+            } else if diff < 0 {
+                // The queue is empty.
+                return None
+            } else {
+                pos = self.dequeue_pos.load(Relaxed);
+            }
+        }
+    }
+
+    /// Returns an iterator that pops values until the queue is empty.
+    ///
+    /// Values pushed by other threads while draining may also be returned.
+    pub fn drain(&self) -> Drain<'_, T> {
+        Drain { queue: self }
+    }
+
+    /// Pop and drop every value in the queue.
+    pub fn clear(&self) {
+        while self.pop().is_some() {}
+    }
+}
+
+impl<T: Send> ConcurrentQueue<T> for LockFreeQueue<T> {
+    fn push(&self, value: T) -> Result<(), T> {
+        LockFreeQueue::push(self, value)
+    }
+
+    fn pop(&self) -> Option<T> {
+        LockFreeQueue::pop(self)
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        Some(LockFreeQueue::capacity(self))
+    }
+
+    fn len(&self) -> usize {
+        LockFreeQueue::len(self)
+    }
+}
+
+impl<T: Send> BoundedQueue<T> for LockFreeQueue<T> {
+    fn with_capacity(cap: usize) -> LockFreeQueue<T> {
+        LockFreeQueue::with_capacity(cap)
+    }
+}
+
+// Releases a node marked by `peek_with`, even if the peek closure panics.
+struct PeekGuard<'a> {
+    peekers: &'a AtomicUsize,
+}
+
+impl<'a> Drop for PeekGuard<'a> {
+    fn drop(&mut self) {
+        self.peekers.fetch_sub(1, Release);
+    }
+}
+
+/// A draining iterator over a LockFreeQueue.
+///
+/// Created by `LockFreeQueue::drain`.
+pub struct Drain<'a, T: 'a> {
+    queue: &'a LockFreeQueue<T>,
+}
+
+impl<'a, T: Send> Iterator for Drain<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.queue.pop()
+    }
 }
 
 #[cfg(test)]
@@ -222,4 +354,78 @@ mod tests {
             rx.recv().unwrap();
         }
     }
+
+    #[test]
+    fn test_capacity_and_len() {
+        let q = LockFreeQueue::with_capacity(5);
+        assert_eq!(q.capacity(), 8);
+        assert!(q.is_empty());
+
+        assert!(q.push(1u8).is_ok());
+        assert!(q.push(2u8).is_ok());
+        assert_eq!(q.len(), 2);
+
+        assert_eq!(q.pop(), Some(1u8));
+        assert_eq!(q.len(), 1);
+    }
+
+    #[test]
+    fn test_peek_with() {
+        let q = LockFreeQueue::with_capacity(4);
+        assert_eq!(q.peek_with(|v: &u8| *v), None);
+
+        assert!(q.push(1u8).is_ok());
+        assert!(q.push(2u8).is_ok());
+        assert_eq!(q.peek_with(|v| *v), Some(1));
+        assert_eq!(q.peek_with(|v| *v + 10), Some(11));
+        assert_eq!(q.len(), 2);
+
+        assert_eq!(q.pop(), Some(1));
+        assert_eq!(q.peek_with(|v| *v), Some(2));
+
+        // Peeking from inside a peek does not wait on itself.
+        assert_eq!(q.peek_with(|v| q.peek_with(|w| *v + *w)), Some(Some(4)));
+        assert_eq!(q.pop(), Some(2));
+    }
+
+    #[test]
+    fn test_drain_clear() {
+        let q = LockFreeQueue::with_capacity(8);
+        for i in 0..5u8 {
+            assert!(q.push(i).is_ok());
+        }
+        assert_eq!(q.drain().collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+        assert!(q.is_empty());
+
+        for i in 0..5u8 {
+            assert!(q.push(i).is_ok());
+        }
+        q.clear();
+        assert_eq!(q.pop(), None);
+    }
+
+    #[test]
+    fn test_peek_concurrent_pop() {
+        let nmsgs = 1000;
+        let q = Arc::new(LockFreeQueue::with_capacity(nmsgs));
+        for i in 0..nmsgs {
+            assert!(q.push(i).is_ok());
+        }
+
+        let peeker = {
+            let q = q.clone();
+            thread::spawn(move || {
+                while let Some(v) = q.peek_with(|v| *v) {
+                    assert!(v < nmsgs);
+                }
+            })
+        };
+
+        let mut popped = 0;
+        while q.pop().is_some() {
+            popped += 1;
+        }
+        peeker.join().unwrap();
+        assert_eq!(popped, nmsgs);
+    }
 }
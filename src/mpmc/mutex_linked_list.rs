@@ -1,63 +1,291 @@
-use std::cell::{RefCell};
+// This is the two-lock queue from "Simple, Fast, and Practical Non-Blocking and
+// Blocking Concurrent Queue Algorithms" by Michael and Scott. The front and back
+// of the list each have their own lock, so pushes and pops do not contend with
+// each other. A stub node always sits at the front, which keeps the two ends
+// apart even when the list is empty.
+
 use std::ptr;
-use std::ops::DerefMut;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize};
+use std::sync::atomic::Ordering::{Acquire, Relaxed, SeqCst};
+use std::time::{Duration, Instant};
+
+use mpmc::{BoundedQueue, ConcurrentQueue};
+
+// How many spare nodes a list keeps for reuse by default.
+const POOL_SIZE: usize = 64;
 
 struct Node<T> {
     value: Option<T>,
-    next: RefCell<*mut Node<T>>,
+    // Read by the front while the back may be writing it, when the list is
+    // empty and the stub is also the last node.
+    next: AtomicPtr<Node<T>>,
+    // Only read with both locks held. The stub's prev is never read.
+    prev: *mut Node<T>,
 }
 
 impl<T> Node<T> {
-    unsafe fn new(value: T) -> *mut Node<T> {
+    fn new(value: Option<T>) -> *mut Node<T> {
         Box::into_raw(Box::new(Node {
-            value: Some(value),
-            next: RefCell::new(ptr::null_mut()),
+            value,
+            next: AtomicPtr::new(ptr::null_mut()),
+            prev: ptr::null_mut(),
         }))
     }
 }
 
+// Nodes freed by pops, kept to be reused by pushes instead of going back to the
+// allocator. The spare nodes are chained through `next` and hold no value.
+struct Pool<T> {
+    free: *mut Node<T>,
+    len: usize,
+    cap: usize,
+}
+
+// The list runs from the stub node at `head` to the most recently pushed node
+// at `tail`. Each pointer is owned by its lock. Anything that touches both
+// ends locks `head` first, then `tail`.
 struct ListInner<T> {
-    lock: Mutex<bool>,
-    head: RefCell<*mut Node<T>>,
-    tail: RefCell<*mut Node<T>>,
+    // The stub node, whose value is always None, just before the next value to
+    // pop.
+    head: Mutex<*mut Node<T>>,
+    // The last node in the list. This is the stub when the list is empty.
+    tail: Mutex<*mut Node<T>>,
+
+    // Signalled, with `head` held, when a value is pushed or the list is closed.
+    not_empty: Condvar,
+    // Threads waiting on `not_empty`. Pushers only take the `head` lock to
+    // signal when this is non-zero.
+    waiters: AtomicUsize,
+
+    // Signalled, with `tail` held, when a value is popped from a bounded list
+    // or the list is closed.
+    not_full: Condvar,
+    // Threads waiting on `not_full`. Poppers only take the `tail` lock to
+    // signal when this is non-zero.
+    full_waiters: AtomicUsize,
+
+    closed: AtomicBool,
+    // Only ever raised with one of the locks held, and never past `cap`.
+    len: AtomicUsize,
+    // The most values the list may hold, None if it is unbounded.
+    cap: Option<usize>,
+
+    // Only ever taken with try_lock, a contended pool falls back to the
+    // allocator rather than making pushes and pops wait on each other.
+    pool: Mutex<Pool<T>>,
 }
 
 unsafe impl<T: Send> Send for ListInner<T> { }
 unsafe impl<T: Send> Sync for ListInner<T> { }
 
 impl<T> ListInner<T> {
-    fn new() -> ListInner<T> {
-        // Initialize a stub ptr in order to correctly set up the tail and head ptrs.
-        let stub = Box::into_raw(Box::new(Node {
-            value: None, next: RefCell::new(ptr::null_mut())
-        }));
+    fn new(cap: Option<usize>) -> ListInner<T> {
+        // Initialize a stub ptr in order to correctly set up the head and tail ptrs.
+        let stub = Node::new(None);
         ListInner {
-            lock: Mutex::new(true),
-            head: RefCell::new(stub),
-            tail: RefCell::new(stub),
+            head: Mutex::new(stub),
+            tail: Mutex::new(stub),
+            not_empty: Condvar::new(),
+            waiters: AtomicUsize::new(0),
+            not_full: Condvar::new(),
+            full_waiters: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            len: AtomicUsize::new(0),
+            cap,
+            pool: Mutex::new(Pool { free: ptr::null_mut(), len: 0, cap: POOL_SIZE }),
+        }
+    }
+
+    // A panic in a peek or retain closure poisons the lock it ran under, but
+    // leaves the list itself intact, so poisoning is ignored throughout.
+    fn lock_head(&self) -> MutexGuard<'_, *mut Node<T>> {
+        self.head.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_tail(&self) -> MutexGuard<'_, *mut Node<T>> {
+        self.tail.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn close(&self) {
+        self.closed.store(true, SeqCst);
+        {
+            let _head = self.lock_head();
+            self.not_empty.notify_all();
+        }
+        let _tail = self.lock_tail();
+        self.not_full.notify_all();
+    }
+
+    // Wake a thread blocked in pop_wait, if there is one.
+    fn notify_not_empty(&self) {
+        if self.waiters.load(SeqCst) != 0 {
+            let _head = self.lock_head();
+            self.not_empty.notify_one();
+        }
+    }
+
+    // Wake a thread blocked in push_wait, if there is one. Must not be called
+    // with `tail` held.
+    fn notify_not_full(&self) {
+        if self.full_waiters.load(SeqCst) != 0 {
+            let _tail = self.lock_tail();
+            self.not_full.notify_one();
+        }
+    }
+
+    // Count a value that is about to be linked in, if there is room for it. The
+    // caller must hold one of the locks.
+    fn reserve(&self) -> bool {
+        match self.cap {
+            None => {
+                self.len.fetch_add(1, SeqCst);
+                true
+            }
+            Some(cap) => {
+                self.len.fetch_update(SeqCst, SeqCst, |len| {
+                    if len < cap { Some(len + 1) } else { None }
+                }).is_ok()
+            }
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.cap.is_some_and(|cap| self.len.load(SeqCst) >= cap)
+    }
+
+    // Take a node from the pool, or allocate one if the pool is empty or busy.
+    fn alloc(&self, value: Option<T>) -> *mut Node<T> {
+        if let Ok(mut pool) = self.pool.try_lock() {
+            let node = pool.free;
+            if !node.is_null() {
+                unsafe {
+                    pool.free = (*node).next.load(Relaxed);
+                    pool.len -= 1;
+                    (*node).value = value;
+                    (*node).next.store(ptr::null_mut(), Relaxed);
+                    (*node).prev = ptr::null_mut();
+                }
+                return node
+            }
+        }
+        Node::new(value)
+    }
+
+    // Return a node that is no longer linked into the list to the pool, or free
+    // it if the pool is full or busy. Any value still in the node is dropped
+    // after the pool is unlocked.
+    unsafe fn release(&self, node: *mut Node<T>) {
+        let _value = (*node).value.take();
+        if let Ok(mut pool) = self.pool.try_lock() {
+            if pool.len < pool.cap {
+                (*node).next.store(pool.free, Relaxed);
+                pool.free = node;
+                pool.len += 1;
+                return
+            }
+        }
+        let _: Box<Node<T>> = Box::from_raw(node);
+    }
+
+    fn set_pool_size(&self, size: usize) {
+        let mut pool = self.pool.lock().unwrap_or_else(PoisonError::into_inner);
+        pool.cap = size;
+        while pool.len > size {
+            unsafe {
+                let node = pool.free;
+                pool.free = (*node).next.load(Relaxed);
+                pool.len -= 1;
+                let _: Box<Node<T>> = Box::from_raw(node);
+            }
         }
     }
 }
 
 /// A mutex-locked List that is safe for push/pop on multiple threads.
+///
+/// Values are normally pushed at the back and popped from the front, but the
+/// list also supports pushing at the front and popping from the back. The front
+/// and back are locked separately, so pushing and popping can happen in
+/// parallel.
 pub struct MutexLinkedList<T> {
     inner: Arc<ListInner<T>>,
 }
 
 impl<T> MutexLinkedList<T> {
-    /// Create a new MutexLinkedList.
+    /// Create a new, unbounded MutexLinkedList.
     pub fn new() -> MutexLinkedList<T> {
         MutexLinkedList {
-            inner: Arc::new(ListInner::new()),
+            inner: Arc::new(ListInner::new(None)),
         }
     }
+
+    /// Create a new MutexLinkedList that holds at most `cap` values.
+    pub fn with_capacity(cap: usize) -> MutexLinkedList<T> {
+        MutexLinkedList {
+            inner: Arc::new(ListInner::new(Some(cap))),
+        }
+    }
+
+    /// Returns the most values the list can hold, or None if it is unbounded.
+    pub fn capacity(&self) -> Option<usize> {
+        self.inner.cap
+    }
+
+    /// Set how many spare nodes the list keeps for reuse.
+    ///
+    /// Popped nodes are kept and handed back out to later pushes, which saves
+    /// an allocation and a free per value. The list keeps up to 64 spare nodes
+    /// by default, a size of 0 turns pooling off.
+    pub fn set_pool_size(&self, size: usize) {
+        self.inner.set_pool_size(size)
+    }
+
+    /// Returns the number of values in the list.
+    pub fn len(&self) -> usize {
+        self.inner.len.load(Relaxed)
+    }
+
+    /// Returns true if the list holds no values.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Close the list, waking every thread blocked in `pop_wait`,
+    /// `pop_timeout` or `push_wait`.
+    ///
+    /// Values already in the list can still be popped, but any further push
+    /// fails.
+    pub fn close(&self) {
+        self.inner.close()
+    }
+
+    /// Returns true if the list has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.inner.closed.load(SeqCst)
+    }
+}
+
+impl<T> Default for MutexLinkedList<T> {
+    fn default() -> MutexLinkedList<T> {
+        MutexLinkedList::new()
+    }
 }
 
 impl<T: Send> MutexLinkedList<T> {
     /// Push a value onto queue.
-    pub fn push(&self, value: T) {
-        self.inner.push(value)
+    ///
+    /// If the queue is full or has been closed, the value is returned in the
+    /// Err(..).
+    pub fn push(&self, value: T) -> Result<(), T> {
+        self.inner.push(value, false)
+    }
+
+    /// Push a value onto the queue, blocking until there is room for it.
+    ///
+    /// If the queue is closed, the value is returned in the Err(..).
+    pub fn push_wait(&self, value: T) -> Result<(), T> {
+        self.inner.push(value, true)
     }
 
     /// Pop a value off the queue.
@@ -66,6 +294,100 @@ impl<T: Send> MutexLinkedList<T> {
     pub fn pop(&self) -> Option<T> {
         self.inner.pop()
     }
+
+    /// Pop a value off the queue, blocking until one is available.
+    ///
+    /// None is returned once the queue is closed and empty.
+    pub fn pop_wait(&self) -> Option<T> {
+        self.inner.pop_wait(None)
+    }
+
+    /// Pop a value off the queue, blocking for at most `timeout` until one is
+    /// available.
+    ///
+    /// None is returned if the timeout expires, or once the queue is closed and
+    /// empty.
+    pub fn pop_timeout(&self, timeout: Duration) -> Option<T> {
+        self.inner.pop_wait(Some(Instant::now() + timeout))
+    }
+
+    /// Push a value onto the front of the queue, so that it is the next value
+    /// popped.
+    ///
+    /// If the queue is full or has been closed, the value is returned in the
+    /// Err(..).
+    pub fn push_front(&self, value: T) -> Result<(), T> {
+        self.inner.push_front(value)
+    }
+
+    /// Pop the most recently pushed value off the back of the queue.
+    ///
+    /// If the queue is empty, None is returned.
+    pub fn pop_back(&self) -> Option<T> {
+        self.inner.pop_back()
+    }
+
+    /// Call `f` with a reference to the value at the front of the queue.
+    ///
+    /// If the queue is empty, None is returned. The front of the queue is
+    /// locked while `f` runs.
+    pub fn peek_front<F, R>(&self, f: F) -> Option<R>
+        where F: FnOnce(&T) -> R
+    {
+        self.inner.peek_front(f)
+    }
+
+    /// Call `f` with a reference to the value at the back of the queue.
+    ///
+    /// If the queue is empty, None is returned. The whole queue is locked while
+    /// `f` runs.
+    pub fn peek_back<F, R>(&self, f: F) -> Option<R>
+        where F: FnOnce(&T) -> R
+    {
+        self.inner.peek_back(f)
+    }
+
+    /// Remove every value for which `f` returns false, keeping the rest in
+    /// order.
+    ///
+    /// The whole queue is locked for the pass.
+    pub fn retain<F>(&self, f: F)
+        where F: FnMut(&T) -> bool
+    {
+        self.inner.retain(f)
+    }
+
+    /// Take every value out of the queue at once.
+    ///
+    /// The values are returned front to back. Values pushed after the call are
+    /// not part of the drain.
+    pub fn drain(&self) -> ListDrain<T> {
+        self.inner.drain()
+    }
+}
+
+impl<T: Send> ConcurrentQueue<T> for MutexLinkedList<T> {
+    fn push(&self, value: T) -> Result<(), T> {
+        MutexLinkedList::push(self, value)
+    }
+
+    fn pop(&self) -> Option<T> {
+        MutexLinkedList::pop(self)
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        MutexLinkedList::capacity(self)
+    }
+
+    fn len(&self) -> usize {
+        MutexLinkedList::len(self)
+    }
+}
+
+impl<T: Send> BoundedQueue<T> for MutexLinkedList<T> {
+    fn with_capacity(cap: usize) -> MutexLinkedList<T> {
+        MutexLinkedList::with_capacity(cap)
+    }
 }
 
 impl<T: Send> Clone for MutexLinkedList<T> {
@@ -75,65 +397,297 @@ impl<T: Send> Clone for MutexLinkedList<T> {
 }
 
 impl<T: Send> ListInner<T> {
-    fn push(&self, value: T) {
-        unsafe {
-            let node = Node::new(value);
+    fn push(&self, value: T, block: bool) -> Result<(), T> {
+        let node = self.alloc(Some(value));
 
-            let _lock = self.lock.lock();
+        {
+            let mut tail = self.lock_tail();
+            loop {
+                if !self.closed.load(SeqCst) && self.reserve() {
+                    break
+                }
+                if !block || self.closed.load(SeqCst) {
+                    drop(tail);
+                    unsafe {
+                        let value = (*node).value.take().unwrap();
+                        self.release(node);
+                        return Err(value)
+                    }
+                }
 
-            let prev = self.head.borrow().clone();
-            *((*prev).next.borrow_mut().deref_mut()) = node;
+                self.full_waiters.fetch_add(1, SeqCst);
+                // A pop may have landed before it could see us waiting.
+                if self.is_full() && !self.closed.load(SeqCst) {
+                    tail = self.not_full.wait(tail).unwrap_or_else(PoisonError::into_inner);
+                }
+                self.full_waiters.fetch_sub(1, SeqCst);
+            }
 
-            *(self.head.borrow_mut().deref_mut()) = node;
+            unsafe {
+                (*node).prev = *tail;
+                // SeqCst pairs with the waiter count in pop_wait, so either the
+                // waiter sees this node or we see the waiter.
+                (**tail).next.store(node, SeqCst);
+            }
+            *tail = node;
         }
+
+        self.notify_not_empty();
+        Ok(())
     }
 
     fn pop(&self) -> Option<T> {
+        let value = self.pop_locked(&mut self.lock_head());
+        if value.is_some() {
+            self.notify_not_full();
+        }
+        value
+    }
+
+    // Wait until a value can be popped, the list is closed, or the deadline
+    // passes.
+    fn pop_wait(&self, deadline: Option<Instant>) -> Option<T> {
+        let mut head = self.lock_head();
+        loop {
+            if let Some(value) = self.pop_locked(&mut head) {
+                drop(head);
+                self.notify_not_full();
+                return Some(value)
+            }
+            if self.closed.load(SeqCst) {
+                return None
+            }
+
+            self.waiters.fetch_add(1, SeqCst);
+            // A push may have landed before it could see us waiting.
+            let ready = unsafe { !(**head).next.load(SeqCst).is_null() };
+            let woken = if ready || self.closed.load(SeqCst) {
+                Some(head)
+            } else {
+                self.wait(head, deadline)
+            };
+            self.waiters.fetch_sub(1, SeqCst);
+            head = woken?;
+        }
+    }
+
+    fn wait<'a>(&self, guard: MutexGuard<'a, *mut Node<T>>, deadline: Option<Instant>)
+        -> Option<MutexGuard<'a, *mut Node<T>>>
+    {
+        match deadline {
+            None => Some(self.not_empty.wait(guard).unwrap_or_else(PoisonError::into_inner)),
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return None
+                }
+                let (guard, _) = self.not_empty.wait_timeout(guard, deadline - now)
+                    .unwrap_or_else(PoisonError::into_inner);
+                Some(guard)
+            }
+        }
+    }
+
+    // The caller must hold the `head` lock, passing its guard.
+    fn pop_locked(&self, head: &mut MutexGuard<'_, *mut Node<T>>) -> Option<T> {
         unsafe {
-            let _lock = self.lock.lock();
-            let old = *(self.tail.borrow_mut().deref_mut());
-            let next = *((*old).next.borrow_mut().deref_mut());
+            let old = **head;
+            let next = (*old).next.load(Acquire);
 
             if !next.is_null() {
                 assert!((*old).value.is_none());
                 assert!((*next).value.is_some());
                 let ret = (*next).value.take().unwrap();
-                *(self.tail.borrow_mut().deref_mut()) = next;
-                let _: Box<Node<T>> = Box::from_raw(old);
+                **head = next;
+                self.release(old);
+                self.len.fetch_sub(1, SeqCst);
                 Some(ret)
             } else {
                 None
             }
         }
     }
+
+    fn push_front(&self, value: T) -> Result<(), T> {
+        // The new value goes into the current stub, and a fresh stub is linked
+        // in ahead of it. Only the front of the list changes.
+        let stub = self.alloc(None);
+
+        let mut head = self.lock_head();
+        if self.closed.load(SeqCst) || !self.reserve() {
+            drop(head);
+            unsafe { self.release(stub) };
+            return Err(value)
+        }
+
+        unsafe {
+            let old = *head;
+            (*old).value = Some(value);
+            (*old).prev = stub;
+            (*stub).next.store(old, Relaxed);
+        }
+        *head = stub;
+
+        // We already hold `head`, so there is no race with a waiter.
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    fn pop_back(&self) -> Option<T> {
+        let head = self.lock_head();
+        let mut tail = self.lock_tail();
+        if *tail == *head {
+            return None
+        }
+
+        unsafe {
+            let last = *tail;
+            let prev = (*last).prev;
+            (*prev).next.store(ptr::null_mut(), Relaxed);
+            *tail = prev;
+            self.len.fetch_sub(1, SeqCst);
+            // We already hold `tail`, so there is no race with a waiter.
+            self.not_full.notify_one();
+
+            let value = (*last).value.take();
+            self.release(last);
+            value
+        }
+    }
+
+    fn peek_front<F, R>(&self, f: F) -> Option<R>
+        where F: FnOnce(&T) -> R
+    {
+        let head = self.lock_head();
+        unsafe {
+            let first = (**head).next.load(Acquire);
+            if first.is_null() {
+                return None
+            }
+            (*first).value.as_ref().map(f)
+        }
+    }
+
+    fn peek_back<F, R>(&self, f: F) -> Option<R>
+        where F: FnOnce(&T) -> R
+    {
+        let head = self.lock_head();
+        let tail = self.lock_tail();
+        if *tail == *head {
+            return None
+        }
+        unsafe { (**tail).value.as_ref().map(f) }
+    }
+
+    fn retain<F>(&self, mut f: F)
+        where F: FnMut(&T) -> bool
+    {
+        let head = self.lock_head();
+        let mut tail = self.lock_tail();
+        unsafe {
+            let mut prev = *head;
+            let mut cur = (*prev).next.load(Relaxed);
+            while !cur.is_null() {
+                let next = (*cur).next.load(Relaxed);
+                if f((*cur).value.as_ref().unwrap()) {
+                    prev = cur;
+                } else {
+                    (*prev).next.store(next, Relaxed);
+                    if next.is_null() {
+                        *tail = prev;
+                    } else {
+                        (*next).prev = prev;
+                    }
+                    self.release(cur);
+                    self.len.fetch_sub(1, SeqCst);
+                }
+                cur = next;
+            }
+        }
+        self.not_full.notify_all();
+    }
+
+    fn drain(&self) -> ListDrain<T> {
+        let head = self.lock_head();
+        let mut tail = self.lock_tail();
+        unsafe {
+            let stub = *head;
+            let first = (*stub).next.swap(ptr::null_mut(), Relaxed);
+            *tail = stub;
+            self.len.store(0, SeqCst);
+            self.not_full.notify_all();
+            ListDrain { next: first }
+        }
+    }
 }
 
 impl<T> Drop for ListInner<T> {
     fn drop(&mut self) {
-        unsafe {
-            let mut cur = *(self.head.borrow_mut().deref_mut());
-            while !cur.is_null() {
-                let next = *((*cur).next.borrow_mut().deref_mut());
-                let _: Box<Node<T>> = Box::from_raw(cur);
-                cur = next
+        let pool_free = self.pool.get_mut().unwrap_or_else(PoisonError::into_inner).free;
+        let head = *self.head.get_mut().unwrap_or_else(PoisonError::into_inner);
+        for first in [head, pool_free] {
+            unsafe {
+                let mut cur = first;
+                while !cur.is_null() {
+                    let next = (*cur).next.load(Relaxed);
+                    let _: Box<Node<T>> = Box::from_raw(cur);
+                    cur = next
+                }
             }
         }
     }
 }
 
+/// An iterator over the values taken out of a MutexLinkedList.
+///
+/// Created by `MutexLinkedList::drain`.
+pub struct ListDrain<T> {
+    next: *mut Node<T>,
+}
+
+unsafe impl<T: Send> Send for ListDrain<T> { }
+
+impl<T> Iterator for ListDrain<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.next.is_null() {
+            return None
+        }
+        unsafe {
+            let mut node: Box<Node<T>> = Box::from_raw(self.next);
+            self.next = node.next.load(Relaxed);
+            node.value.take()
+        }
+    }
+}
+
+impl<T> Drop for ListDrain<T> {
+    fn drop(&mut self) {
+        for _ in self {}
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::MutexLinkedList;
-    use std::thread::spawn;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::thread::{sleep, spawn};
+    use std::time::Duration;
+
+    // Iteration counts are kept small so the tests also run in reasonable time
+    // under Miri and the thread sanitizer.
 
     #[test]
     fn test_push_pop() {
         let q = MutexLinkedList::new();
         assert!(q.pop().is_none());
-        q.push(1);
-        q.push(2);
+        assert!(q.push(1).is_ok());
+        assert!(q.push(2).is_ok());
+        assert_eq!(q.len(), 2);
         assert_eq!(q.pop().unwrap(), 1);
         assert_eq!(q.pop().unwrap(), 2);
+        assert!(q.is_empty());
     }
 
     #[test]
@@ -143,7 +697,7 @@ mod test {
         for i in 0..10 {
             let qu = q.clone();
             guard_vec.push(spawn(move || {
-                qu.push(i as u8);
+                assert!(qu.push(i as u8).is_ok());
             }));
         }
 
@@ -170,4 +724,251 @@ mod test {
             thr.join().unwrap();
         }
     }
+
+    #[test]
+    fn test_pop_wait() {
+        let q = MutexLinkedList::new();
+        let qu = q.clone();
+        let waiter = spawn(move || {
+            assert_eq!(qu.pop_wait(), Some(1u8));
+            assert_eq!(qu.pop_wait(), Some(2u8));
+            assert_eq!(qu.pop_wait(), None);
+        });
+
+        sleep(Duration::from_millis(10));
+        assert!(q.push(1).is_ok());
+        assert!(q.push(2).is_ok());
+        q.close();
+        assert!(q.is_closed());
+        assert_eq!(q.push(3), Err(3));
+        waiter.join().unwrap();
+    }
+
+    #[test]
+    fn test_pop_timeout() {
+        let q = MutexLinkedList::new();
+        assert_eq!(q.pop_timeout(Duration::from_millis(10)), None);
+        assert!(q.push(1u8).is_ok());
+        assert_eq!(q.pop_timeout(Duration::from_millis(10)), Some(1));
+    }
+
+    #[test]
+    fn test_with_capacity() {
+        let q = MutexLinkedList::with_capacity(2);
+        assert_eq!(q.capacity(), Some(2));
+        assert!(q.push(1u8).is_ok());
+        assert!(q.push(2).is_ok());
+        assert_eq!(q.push(3), Err(3));
+        assert_eq!(q.push_front(0), Err(0));
+
+        let qu = q.clone();
+        let pusher = spawn(move || {
+            assert!(qu.push_wait(3).is_ok());
+            assert_eq!(qu.push_wait(4), Err(4));
+        });
+
+        sleep(Duration::from_millis(10));
+        assert_eq!(q.pop(), Some(1));
+        while q.len() < 2 {
+            sleep(Duration::from_millis(1));
+        }
+        q.close();
+        pusher.join().unwrap();
+
+        assert_eq!(q.pop(), Some(2));
+        assert_eq!(q.pop(), Some(3));
+        assert!(q.pop().is_none());
+    }
+
+    #[test]
+    fn test_node_pool() {
+        let q = MutexLinkedList::new();
+        let pooled = |q: &MutexLinkedList<u8>| q.inner.pool.lock().unwrap().len;
+
+        for i in 0..10 {
+            assert!(q.push(i).is_ok());
+        }
+        for i in 0..10 {
+            assert_eq!(q.pop(), Some(i));
+        }
+        assert_eq!(pooled(&q), 10);
+
+        // Pushes reuse the pooled nodes.
+        assert!(q.push(1).is_ok());
+        assert!(q.push_front(0).is_ok());
+        assert_eq!(pooled(&q), 8);
+
+        q.set_pool_size(4);
+        assert_eq!(pooled(&q), 4);
+        assert_eq!(q.pop_back(), Some(1));
+        assert_eq!(q.push(2), Ok(()));
+        q.retain(|_| false);
+        assert_eq!(pooled(&q), 4);
+
+        q.set_pool_size(0);
+        assert_eq!(pooled(&q), 0);
+        assert!(q.push(3).is_ok());
+        assert_eq!(q.pop(), Some(3));
+        assert_eq!(pooled(&q), 0);
+    }
+
+    #[test]
+    fn test_deque_ops() {
+        let q = MutexLinkedList::new();
+        assert!(q.pop_back().is_none());
+        assert!(q.peek_front(|v: &u8| *v).is_none());
+        assert!(q.peek_back(|v: &u8| *v).is_none());
+
+        assert!(q.push(2).is_ok());
+        assert!(q.push(3).is_ok());
+        assert!(q.push_front(1).is_ok());
+        assert!(q.push_front(0).is_ok());
+        assert_eq!(q.len(), 4);
+        assert_eq!(q.peek_front(|v| *v), Some(0));
+        assert_eq!(q.peek_back(|v| *v), Some(3));
+
+        assert_eq!(q.pop_back(), Some(3));
+        assert_eq!(q.pop(), Some(0));
+        assert_eq!(q.pop_back(), Some(2));
+        assert_eq!(q.pop_back(), Some(1));
+        assert!(q.pop_back().is_none());
+        assert!(q.pop().is_none());
+
+        // The list still works once emptied from the back.
+        assert!(q.push(4).is_ok());
+        assert!(q.push_front(5).is_ok());
+        assert_eq!(q.pop(), Some(5));
+        assert_eq!(q.pop(), Some(4));
+    }
+
+    #[test]
+    fn test_retain_drain() {
+        let q = MutexLinkedList::new();
+        for i in 0..10u8 {
+            assert!(q.push(i).is_ok());
+        }
+
+        q.retain(|v| v % 3 != 0);
+        assert_eq!(q.len(), 6);
+        assert_eq!(q.peek_back(|v| *v), Some(8));
+        assert!(q.push(10).is_ok());
+
+        assert_eq!(q.drain().collect::<Vec<_>>(), vec![1, 2, 4, 5, 7, 8, 10]);
+        assert!(q.is_empty());
+        assert!(q.pop().is_none());
+        assert!(q.push(11).is_ok());
+        assert_eq!(q.pop_back(), Some(11));
+    }
+
+    #[test]
+    fn test_panicking_closure() {
+        let q = MutexLinkedList::new();
+        for i in 0..3u8 {
+            assert!(q.push(i).is_ok());
+        }
+
+        let peek = catch_unwind(AssertUnwindSafe(|| q.peek_front(|_| panic!("peek"))));
+        assert!(peek.is_err());
+        let retain = catch_unwind(AssertUnwindSafe(|| q.retain(|v| *v < 1 || panic!("retain"))));
+        assert!(retain.is_err());
+
+        // The list is still usable after the closures poisoned its locks.
+        assert!(q.push(3).is_ok());
+        assert_eq!(q.pop(), Some(0));
+        assert_eq!(q.pop_back(), Some(3));
+        assert_eq!(q.len(), 2);
+    }
+
+    #[test]
+    fn test_parallel_push_pop() {
+        let q = MutexLinkedList::new();
+
+        let producers = (0..4).map(|t| {
+            let qu = q.clone();
+            spawn(move || {
+                for i in 0..50 {
+                    assert!(qu.push(t * 50 + i).is_ok());
+                }
+            })
+        }).collect::<Vec<_>>();
+
+        let consumers = (0..2).map(|_| {
+            let qu = q.clone();
+            spawn(move || {
+                let mut sum = 0;
+                while let Some(v) = qu.pop_wait() {
+                    sum += v;
+                }
+                sum
+            })
+        }).collect::<Vec<_>>();
+
+        // Mix in operations that lock both ends.
+        for _ in 0..20 {
+            q.retain(|_| true);
+            let _ = q.peek_back(|v| *v);
+        }
+
+        for thr in producers.into_iter() {
+            thr.join().unwrap();
+        }
+        q.close();
+
+        let sum: usize = consumers.into_iter().map(|thr| thr.join().unwrap()).sum();
+        assert_eq!(sum, 200 * 199 / 2);
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn test_parallel_near_empty() {
+        // A capacity of 2 keeps the list at or near empty, where push and pop
+        // touch the same node under different locks.
+        let q = MutexLinkedList::with_capacity(2);
+
+        let producers = (0..2).map(|t| {
+            let qu = q.clone();
+            spawn(move || {
+                for i in 0..50 {
+                    assert!(qu.push_wait(t * 50 + i).is_ok());
+                }
+            })
+        }).collect::<Vec<_>>();
+
+        // Moves values from the back to the front, which locks both ends.
+        let mixer = {
+            let qu = q.clone();
+            spawn(move || {
+                for _ in 0..50 {
+                    if let Some(v) = qu.pop_back() {
+                        // The slot just freed may already be taken again.
+                        let mut v = v;
+                        while let Err(back) = qu.push_front(v) {
+                            v = back;
+                            sleep(Duration::from_millis(1));
+                        }
+                    }
+                }
+            })
+        };
+
+        let consumers = (0..2).map(|_| {
+            let qu = q.clone();
+            spawn(move || {
+                let mut sum = 0;
+                while let Some(v) = qu.pop_wait() {
+                    sum += v;
+                }
+                sum
+            })
+        }).collect::<Vec<_>>();
+
+        for thr in producers.into_iter().chain(Some(mixer)) {
+            thr.join().unwrap();
+        }
+        q.close();
+
+        let sum: usize = consumers.into_iter().map(|thr| thr.join().unwrap()).sum();
+        assert_eq!(sum, 100 * 99 / 2);
+        assert!(q.is_empty());
+    }
 }
@@ -0,0 +1,166 @@
+//! A multi-producer, single-consumer channel.
+//!
+//! The channel is unbounded. Sending never blocks and never takes a lock, which
+//! makes it suited to actor mailboxes and other many-to-one fan-in.
+
+mod queue;
+
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::{Acquire, Release, Relaxed};
+use std::thread;
+
+use mpmc::Failure;
+use self::queue::{Queue, PopResult};
+
+struct Shared<T> {
+    queue: Queue<T>,
+    // The number of Senders still alive.
+    senders: AtomicUsize,
+    receiver_gone: AtomicBool,
+}
+
+/// The sending-half of the mpsc channel.
+pub struct Sender<T: Send> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Send> Sender<T> {
+    /// Sends data to the channel.
+    ///
+    /// This method will never block. If the Receiver has disconnected, the value
+    /// is returned in the Err(..).
+    pub fn send(&self, value: T) -> Result<(), T> {
+        if self.shared.receiver_gone.load(Acquire) {
+            return Err(value)
+        }
+        self.shared.queue.push(value);
+        Ok(())
+    }
+}
+
+impl<T: Send> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.shared.senders.fetch_add(1, Relaxed);
+        Sender { shared: self.shared.clone() }
+    }
+}
+
+impl<T: Send> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.senders.fetch_sub(1, Release);
+    }
+}
+
+/// The receiving-half of the mpsc channel.
+///
+/// There is only ever one Receiver, it cannot be cloned or shared between
+/// threads.
+pub struct Receiver<T: Send> {
+    shared: Arc<Shared<T>>,
+    // The queue only supports one consumer at a time, so a Receiver is not Sync.
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+impl<T: Send> Receiver<T> {
+    /// Receive data from the channel without blocking.
+    pub fn try_recv(&self) -> Result<T, Failure> {
+        match unsafe { self.shared.queue.pop() } {
+            PopResult::Data(data) => Ok(data),
+            // A sender is mid-push, the value is not reachable yet.
+            PopResult::Inconsistent => Err(Failure::Empty),
+            PopResult::Empty => {
+                if self.shared.senders.load(Acquire) != 0 {
+                    return Err(Failure::Empty)
+                }
+                // Every sender is gone, but the last pushes may have landed
+                // after the pop above.
+                match unsafe { self.shared.queue.pop() } {
+                    PopResult::Data(data) => Ok(data),
+                    _ => Err(Failure::Disconnected),
+                }
+            }
+        }
+    }
+
+    /// Receive data from the channel.
+    ///
+    /// This method will block until either new data is sent or all senders have
+    /// disconnected.
+    pub fn recv(&self) -> Result<T, Failure> {
+        loop {
+            match self.try_recv() {
+                Err(Failure::Empty) => {}
+                data => { return data },
+            }
+
+            thread::yield_now();
+        }
+    }
+}
+
+impl<T: Send> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver_gone.store(true, Release);
+    }
+}
+
+/// Create an unbounded (Sender<T>, Receiver<T>) pair.
+pub fn mpsc_channel<T: Send>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        queue: Queue::new(),
+        senders: AtomicUsize::new(1),
+        receiver_gone: AtomicBool::new(false),
+    });
+    let sn = Sender { shared: shared.clone() };
+    let rc = Receiver { shared, _not_sync: PhantomData };
+    (sn, rc)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use mpmc::Failure;
+    use mpsc::mpsc_channel;
+
+    #[test]
+    fn test_producer_consumer() {
+        let (sn, rc) = mpsc_channel();
+
+        let mut send_vec = Vec::new();
+        for i in 0..20 {
+            let s = sn.clone();
+            send_vec.push(thread::spawn(move || {
+                for _ in 0..100 {
+                    assert!(s.send(i as u8).is_ok());
+                }
+            }));
+        }
+        drop(sn);
+
+        let mut counts = [0; 20];
+        while let Ok(i) = rc.recv() {
+            counts[i as usize] += 1;
+        }
+        assert!(counts.iter().all(|&c| c == 100));
+
+        for x in send_vec.into_iter() {
+            x.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (sn, rc) = mpsc_channel();
+        match rc.try_recv() {
+            Err(Failure::Empty) => {}
+            res => panic!("expected empty, got {:?}", res),
+        }
+
+        assert!(sn.send(1u8).is_ok());
+        drop(rc);
+        assert_eq!(sn.send(2u8), Err(2));
+    }
+}
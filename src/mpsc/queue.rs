@@ -0,0 +1,154 @@
+// http://www.1024cores.net/home/lock-free-algorithms/queues/non-intrusive-mpsc-node-based-queue
+//
+// Dmitry Vyukov's multi-producer, single-consumer node queue. Like the
+// MutexLinkedList it keeps a stub node between the producers' end and the
+// consumer's end, but producers link themselves in with a single atomic swap
+// instead of taking a lock.
+
+use std::cell::UnsafeCell;
+use std::ptr;
+use std::sync::atomic::AtomicPtr;
+use std::sync::atomic::Ordering::{Acquire, Release, AcqRel};
+
+use CachePadded;
+
+struct Node<T> {
+    next: AtomicPtr<Node<T>>,
+    value: Option<T>,
+}
+
+impl<T> Node<T> {
+    fn new(value: Option<T>) -> *mut Node<T> {
+        Box::into_raw(Box::new(Node {
+            next: AtomicPtr::new(ptr::null_mut()),
+            value,
+        }))
+    }
+}
+
+/// Result of popping from the queue.
+pub enum PopResult<T> {
+    /// A value was popped.
+    Data(T),
+    /// The queue is empty.
+    Empty,
+    /// A producer is part way through a push. The queue is not empty, but the
+    /// next value cannot be reached until the push completes.
+    Inconsistent,
+}
+
+pub struct Queue<T> {
+    // Most recently pushed node. Producers swap themselves in here.
+    head: CachePadded<AtomicPtr<Node<T>>>,
+    // Stub node preceding the next value to pop. Only the consumer touches this.
+    tail: CachePadded<UnsafeCell<*mut Node<T>>>,
+}
+
+unsafe impl<T: Send> Send for Queue<T> {}
+unsafe impl<T: Send> Sync for Queue<T> {}
+
+impl<T> Queue<T> {
+    pub fn new() -> Queue<T> {
+        let stub = Node::new(None);
+        Queue {
+            head: CachePadded::new(AtomicPtr::new(stub)),
+            tail: CachePadded::new(UnsafeCell::new(stub)),
+        }
+    }
+
+    pub fn push(&self, value: T) {
+        unsafe {
+            let node = Node::new(Some(value));
+            let prev = self.head.swap(node, AcqRel);
+            (*prev).next.store(node, Release);
+        }
+    }
+
+    // Only one thread may pop at a time, which is up to the caller to ensure.
+    pub unsafe fn pop(&self) -> PopResult<T> {
+        let tail = *self.tail.get();
+        let next = (*tail).next.load(Acquire);
+
+        if !next.is_null() {
+            *self.tail.get() = next;
+            assert!((*tail).value.is_none());
+            assert!((*next).value.is_some());
+            let ret = (*next).value.take().unwrap();
+            let _: Box<Node<T>> = Box::from_raw(tail);
+            return PopResult::Data(ret)
+        }
+
+        if self.head.load(Acquire) == tail {
+            PopResult::Empty
+        } else {
+            PopResult::Inconsistent
+        }
+    }
+}
+
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let mut cur = *self.tail.get();
+            while !cur.is_null() {
+                let next = (*cur).next.load(Acquire);
+                let _: Box<Node<T>> = Box::from_raw(cur);
+                cur = next;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use super::{Queue, PopResult};
+
+    #[test]
+    fn test_push_pop() {
+        let q = Queue::new();
+        unsafe {
+            match q.pop() {
+                PopResult::Empty => {}
+                _ => panic!("queue should be empty"),
+            }
+            q.push(1u8);
+            q.push(2u8);
+            match (q.pop(), q.pop()) {
+                (PopResult::Data(1), PopResult::Data(2)) => {}
+                _ => panic!("values popped out of order"),
+            }
+        }
+        // Leave a value behind for Drop to clean up.
+        q.push(3u8);
+    }
+
+    #[test]
+    fn test_concurrent_push() {
+        let nthreads = 8;
+        let nmsgs = 1000;
+        let q = Arc::new(Queue::new());
+
+        let producers = (0..nthreads).map(|_| {
+            let q = q.clone();
+            thread::spawn(move || {
+                for i in 0..nmsgs {
+                    q.push(i);
+                }
+            })
+        }).collect::<Vec<_>>();
+
+        let mut popped = 0;
+        while popped < nthreads * nmsgs {
+            match unsafe { q.pop() } {
+                PopResult::Data(_) => popped += 1,
+                PopResult::Empty | PopResult::Inconsistent => thread::yield_now(),
+            }
+        }
+
+        for thr in producers.into_iter() {
+            thr.join().unwrap();
+        }
+    }
+}
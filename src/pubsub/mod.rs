@@ -0,0 +1,291 @@
+//! Topic based publish/subscribe, built on `broadcast`.
+//!
+//! A `Hub` keeps one `Broadcast` per topic. Subscribing to a topic creates it,
+//! and it is removed again once its last `Subscription` is dropped. Consumers
+//! can also subscribe to every topic that matches a predicate or a prefix, in
+//! which case they receive each message along with its topic.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use broadcast::{Broadcast, BroadcastError, Consumer};
+
+struct Topic<T> {
+    broadcast: Broadcast<T>,
+    subscribers: usize,
+}
+
+struct HubInner<K, T> {
+    topics: HashMap<K, Topic<T>>,
+    // Every subscription to more than one topic shares this broadcast, each
+    // with its own filter.
+    patterns: Broadcast<(K, T)>,
+}
+
+/// Routes published messages to the subscribers of their topic.
+///
+/// A Hub can be cloned to publish or subscribe from several threads, every
+/// clone shares the same topics.
+pub struct Hub<K, T> {
+    inner: Arc<Mutex<HubInner<K, T>>>,
+}
+
+impl<K: Eq + Hash + Clone, T: Send> Hub<K, T> {
+    /// Create a new Hub with no topics.
+    pub fn new() -> Hub<K, T> {
+        let inner = HubInner { topics: HashMap::new(), patterns: Broadcast::new() };
+        Hub { inner: Arc::new(Mutex::new(inner)) }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HubInner<K, T>> {
+        self.inner.lock().unwrap()
+    }
+
+    /// Subscribe to every message published on `topic` from now on.
+    pub fn subscribe(&self, topic: K) -> Subscription<K, T> {
+        let mut inner = self.lock();
+        let entry = inner.topics.entry(topic.clone()).or_insert_with(|| {
+            Topic { broadcast: Broadcast::new(), subscribers: 0 }
+        });
+        entry.subscribers += 1;
+        Subscription {
+            consumer: entry.broadcast.consume(),
+            topic,
+            hub: self.inner.clone(),
+        }
+    }
+
+    /// Returns the number of topics that have at least one subscriber.
+    ///
+    /// Subscriptions made with `subscribe_matching`, `subscribe_prefix` or
+    /// `subscribe_all` do not count.
+    pub fn topic_count(&self) -> usize {
+        self.lock().topics.len()
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + 'static, T: Send + 'static> Hub<K, T> {
+    /// Subscribe to every message published on a topic for which `pred`
+    /// returns true.
+    ///
+    /// Messages are received along with the topic they were published on.
+    pub fn subscribe_matching<F>(&self, pred: F) -> Consumer<(K, T)>
+        where F: Fn(&K) -> bool + Send + Sync + 'static
+    {
+        self.lock().patterns.consume_filtered(move |msg: &(K, T)| pred(&msg.0))
+    }
+
+    /// Subscribe to every message published on any topic.
+    pub fn subscribe_all(&self) -> Consumer<(K, T)> {
+        self.subscribe_matching(|_| true)
+    }
+}
+
+impl<K: Eq + Hash + Clone + AsRef<str> + Send + 'static, T: Send + 'static> Hub<K, T> {
+    /// Subscribe to every message published on a topic that starts with
+    /// `prefix`.
+    pub fn subscribe_prefix(&self, prefix: &str) -> Consumer<(K, T)> {
+        let prefix = prefix.to_owned();
+        self.subscribe_matching(move |topic: &K| topic.as_ref().starts_with(&prefix))
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send, T: Clone + Send> Hub<K, T> {
+    /// Publish a message on `topic`.
+    ///
+    /// Returns the number of subscribers the message was sent to, counting
+    /// both subscribers to the topic and matching pattern subscribers.
+    pub fn publish(&self, topic: K, msg: T) -> usize {
+        // Only look the broadcasts up with the Hub locked, so publishing on one
+        // topic does not hold up the others.
+        let (broadcast, patterns) = {
+            let inner = self.lock();
+            let broadcast = inner.topics.get(&topic).map(|entry| entry.broadcast.clone());
+            (broadcast, inner.patterns.clone())
+        };
+
+        let mut sent = 0;
+        if let Some(broadcast) = broadcast {
+            sent += broadcast.send(msg.clone()).unwrap_or(0);
+        }
+        sent + patterns.send((topic, msg)).unwrap_or(0)
+    }
+}
+
+impl<K: Eq + Hash + Clone, T: Send> Default for Hub<K, T> {
+    fn default() -> Hub<K, T> {
+        Hub::new()
+    }
+}
+
+impl<K, T> Clone for Hub<K, T> {
+    fn clone(&self) -> Hub<K, T> {
+        Hub { inner: self.inner.clone() }
+    }
+}
+
+/// A subscription to a single topic of a Hub.
+///
+/// The topic is removed from the Hub once every Subscription to it has been
+/// dropped. Cloning a Subscription counts as another subscriber.
+pub struct Subscription<K: Eq + Hash, T> {
+    consumer: Consumer<T>,
+    topic: K,
+    hub: Arc<Mutex<HubInner<K, T>>>,
+}
+
+impl<K: Eq + Hash, T> Subscription<K, T> {
+    /// Returns the topic this subscription receives messages from.
+    pub fn topic(&self) -> &K {
+        &self.topic
+    }
+
+    /// Returns true if the topic's broadcast has been closed.
+    ///
+    /// See `Consumer::is_closed`.
+    pub fn is_closed(&self) -> bool {
+        self.consumer.is_closed()
+    }
+}
+
+impl<K: Eq + Hash, T: Send> Subscription<K, T> {
+    /// Receive a message published on the topic.
+    ///
+    /// This function will block. See `Consumer::recv`.
+    pub fn recv(&self) -> Result<T, BroadcastError<T>> {
+        self.consumer.recv()
+    }
+
+    /// Receive a message published on the topic without blocking.
+    ///
+    /// See `Consumer::try_recv`.
+    pub fn try_recv(&self) -> Result<T, BroadcastError<T>> {
+        self.consumer.try_recv()
+    }
+
+    /// Receive a message published on the topic, blocking for at most
+    /// `timeout`.
+    ///
+    /// See `Consumer::recv_timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, BroadcastError<T>> {
+        self.consumer.recv_timeout(timeout)
+    }
+
+    /// Receive a message published on the topic, blocking until `deadline` at
+    /// the latest.
+    ///
+    /// See `Consumer::recv_deadline`.
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, BroadcastError<T>> {
+        self.consumer.recv_deadline(deadline)
+    }
+}
+
+impl<K: Eq + Hash + Clone, T: Send> Clone for Subscription<K, T> {
+    fn clone(&self) -> Subscription<K, T> {
+        let mut hub = self.hub.lock().unwrap();
+        let entry = hub.topics.get_mut(&self.topic).unwrap();
+        entry.subscribers += 1;
+        Subscription {
+            consumer: self.consumer.clone(),
+            topic: self.topic.clone(),
+            hub: self.hub.clone(),
+        }
+    }
+}
+
+impl<K: Eq + Hash, T> Drop for Subscription<K, T> {
+    fn drop(&mut self) {
+        let mut hub = self.hub.lock().unwrap();
+        let last = {
+            let entry = hub.topics.get_mut(&self.topic).unwrap();
+            entry.subscribers -= 1;
+            entry.subscribers == 0
+        };
+        if last {
+            hub.topics.remove(&self.topic);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Hub;
+    use broadcast::BroadcastError;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn publish_subscribe() {
+        let hub = Hub::new();
+        let prices = hub.subscribe("prices");
+        let prices2 = prices.clone();
+        let news = hub.subscribe("news");
+        assert_eq!(prices.topic(), &"prices");
+
+        assert_eq!(hub.publish("prices", 10u8), 2);
+        assert_eq!(hub.publish("news", 20), 1);
+        assert_eq!(hub.publish("weather", 30), 0);
+
+        assert_eq!(prices.recv(), Ok(10));
+        assert_eq!(prices2.recv(), Ok(10));
+        assert_eq!(prices.try_recv(), Err(BroadcastError::Empty));
+        assert_eq!(news.recv(), Ok(20));
+        assert!(!news.is_closed());
+    }
+
+    #[test]
+    fn publish_does_not_hold_the_hub() {
+        // A pattern filter runs while publishing. If the Hub were still locked
+        // it could not subscribe here without deadlocking.
+        let hub = Hub::<&str, u8>::new();
+        // Weak, so the filter does not keep its own Hub alive.
+        let weak = Arc::downgrade(&hub.inner);
+        let subscribed = Arc::new(Mutex::new(Vec::new()));
+        let keep = subscribed.clone();
+        let _all = hub.subscribe_matching(move |topic: &&str| {
+            if let (Some(inner), Ok(mut subs)) = (weak.upgrade(), keep.try_lock()) {
+                subs.push(Hub { inner }.subscribe(*topic));
+            }
+            true
+        });
+
+        assert_eq!(hub.publish("a", 1), 1);
+        assert_eq!(hub.topic_count(), 1);
+        subscribed.lock().unwrap().clear();
+        assert_eq!(hub.topic_count(), 0);
+    }
+
+    #[test]
+    fn prefix_and_all() {
+        let hub = Hub::new();
+        let stocks = hub.subscribe_prefix("stocks.");
+        let all = hub.subscribe_all();
+        let _msft = hub.subscribe(String::from("stocks.msft"));
+
+        assert_eq!(hub.publish(String::from("stocks.msft"), 1u8), 3);
+        assert_eq!(hub.publish(String::from("bonds.us"), 2), 1);
+
+        assert_eq!(stocks.recv(), Ok((String::from("stocks.msft"), 1)));
+        assert_eq!(stocks.try_recv(), Err(BroadcastError::Empty));
+        assert_eq!(all.recv(), Ok((String::from("stocks.msft"), 1)));
+        assert_eq!(all.recv(), Ok((String::from("bonds.us"), 2)));
+    }
+
+    #[test]
+    fn topic_cleanup() {
+        let hub = Hub::<&str, u8>::new();
+        let a = hub.subscribe("a");
+        let a2 = a.clone();
+        let b = hub.subscribe("b");
+        assert_eq!(hub.topic_count(), 2);
+
+        drop(a);
+        assert_eq!(hub.topic_count(), 2);
+        drop(a2);
+        assert_eq!(hub.topic_count(), 1);
+        assert_eq!(hub.publish("a", 1), 0);
+        drop(b);
+        assert_eq!(hub.topic_count(), 0);
+    }
+}
@@ -0,0 +1,244 @@
+//! A single-producer, single-consumer channel.
+//!
+//! The channel is a bounded ring buffer. Since only one thread ever writes each
+//! end, pushing and popping are wait-free: neither side uses a CAS or retries.
+
+use std::cell::{Cell, UnsafeCell};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::{Acquire, Release, Relaxed};
+use std::thread;
+
+use mpmc::Failure;
+use CachePadded;
+
+struct Buffer<T> {
+    slots: Vec<UnsafeCell<Option<T>>>,
+    mask: usize,
+
+    // Next position the consumer will read. Only the consumer writes this.
+    head: CachePadded<AtomicUsize>,
+    // Next position the producer will write. Only the producer writes this.
+    tail: CachePadded<AtomicUsize>,
+
+    // Set by whichever of the Producer or Consumer is dropped first.
+    disconnected: AtomicBool,
+}
+
+unsafe impl<T: Send> Send for Buffer<T> {}
+unsafe impl<T: Send> Sync for Buffer<T> {}
+
+impl<T> Buffer<T> {
+    fn with_capacity(cap: usize) -> Buffer<T> {
+        let cap = cap.next_power_of_two();
+        Buffer {
+            slots: (0..cap).map(|_| UnsafeCell::new(None)).collect(),
+            mask: cap - 1,
+            head: CachePadded::new(AtomicUsize::new(0)),
+            tail: CachePadded::new(AtomicUsize::new(0)),
+            disconnected: AtomicBool::new(false),
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.mask + 1
+    }
+}
+
+/// The sending-half of the spsc channel.
+///
+/// A Producer cannot be cloned or shared between threads, but it can be sent to
+/// another thread.
+pub struct Producer<T> {
+    buffer: Arc<Buffer<T>>,
+    // Cached copy of `buffer.head`, only refreshed when the buffer looks full.
+    head: Cell<usize>,
+}
+
+impl<T: Send> Producer<T> {
+    /// Push a value onto the channel.
+    ///
+    /// This method will never block. If the channel is full or the Consumer has
+    /// disconnected, the value is returned in the Err(..).
+    pub fn push(&self, value: T) -> Result<(), T> {
+        if self.buffer.disconnected.load(Acquire) {
+            return Err(value)
+        }
+
+        let tail = self.buffer.tail.load(Relaxed);
+        if tail.wrapping_sub(self.head.get()) == self.buffer.capacity() {
+            self.head.set(self.buffer.head.load(Acquire));
+            if tail.wrapping_sub(self.head.get()) == self.buffer.capacity() {
+                // The channel is full.
+                return Err(value)
+            }
+        }
+
+        unsafe {
+            *self.buffer.slots[tail & self.buffer.mask].get() = Some(value);
+        }
+        self.buffer.tail.store(tail.wrapping_add(1), Release);
+        Ok(())
+    }
+
+    /// Returns the number of values the channel can hold.
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+
+    /// Returns true if the Consumer has been dropped.
+    pub fn is_disconnected(&self) -> bool {
+        self.buffer.disconnected.load(Acquire)
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.buffer.disconnected.store(true, Release);
+    }
+}
+
+/// The receiving-half of the spsc channel.
+///
+/// A Consumer cannot be cloned or shared between threads, but it can be sent to
+/// another thread.
+pub struct Consumer<T> {
+    buffer: Arc<Buffer<T>>,
+    // Cached copy of `buffer.tail`, only refreshed when the buffer looks empty.
+    tail: Cell<usize>,
+}
+
+impl<T: Send> Consumer<T> {
+    /// Pop a value off the channel.
+    ///
+    /// If the channel is empty, None is returned.
+    pub fn pop(&self) -> Option<T> {
+        let head = self.buffer.head.load(Relaxed);
+        if head == self.tail.get() {
+            self.tail.set(self.buffer.tail.load(Acquire));
+            if head == self.tail.get() {
+                // The channel is empty.
+                return None
+            }
+        }
+
+        let value = unsafe {
+            (*self.buffer.slots[head & self.buffer.mask].get()).take()
+        };
+        self.buffer.head.store(head.wrapping_add(1), Release);
+        value
+    }
+
+    /// Receive data from the channel without blocking.
+    pub fn try_recv(&self) -> Result<T, Failure> {
+        match self.pop() {
+            Some(data) => Ok(data),
+            None if self.buffer.disconnected.load(Acquire) => {
+                // The Producer may have pushed a final value before it dropped.
+                self.pop().ok_or(Failure::Disconnected)
+            }
+            None => Err(Failure::Empty),
+        }
+    }
+
+    /// Receive data from the channel.
+    ///
+    /// This method will block until either new data is sent or the Producer has
+    /// disconnected.
+    pub fn recv(&self) -> Result<T, Failure> {
+        loop {
+            match self.try_recv() {
+                Err(Failure::Empty) => {}
+                data => { return data },
+            }
+
+            thread::yield_now();
+        }
+    }
+
+    /// Returns the number of values the channel can hold.
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        self.buffer.disconnected.store(true, Release);
+    }
+}
+
+/// Create a (Producer<T>, Consumer<T>) pair with the specified capacity.
+///
+/// The capacity is rounded up to the next power of 2.
+pub fn spsc_channel<T: Send>(cap: usize) -> (Producer<T>, Consumer<T>) {
+    let buffer = Arc::new(Buffer::with_capacity(cap));
+    let producer = Producer { buffer: buffer.clone(), head: Cell::new(0) };
+    let consumer = Consumer { buffer, tail: Cell::new(0) };
+    (producer, consumer)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use mpmc::Failure;
+    use spsc::spsc_channel;
+
+    #[test]
+    fn test_push_pop() {
+        let (p, c) = spsc_channel(3);
+        assert_eq!(p.capacity(), 4);
+        assert!(c.pop().is_none());
+
+        for i in 0..4u8 {
+            assert!(p.push(i).is_ok());
+        }
+        assert_eq!(p.push(4u8), Err(4));
+
+        for i in 0..4u8 {
+            assert_eq!(c.pop(), Some(i));
+        }
+        assert!(c.pop().is_none());
+        assert!(p.push(4u8).is_ok());
+        assert_eq!(c.pop(), Some(4));
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (p, c) = spsc_channel(4);
+        assert!(p.push(1u8).is_ok());
+        drop(p);
+        assert_eq!(c.recv().unwrap(), 1);
+        match c.recv() {
+            Err(Failure::Disconnected) => {}
+            res => panic!("expected disconnect, got {:?}", res),
+        }
+
+        let (p, c) = spsc_channel(4);
+        drop(c);
+        assert!(p.is_disconnected());
+        assert_eq!(p.push(1u8), Err(1));
+    }
+
+    #[test]
+    fn test_threads() {
+        let nmsgs = 10000;
+        let (p, c) = spsc_channel(16);
+
+        let producer = thread::spawn(move || {
+            for i in 0..nmsgs {
+                let mut value = i;
+                while let Err(v) = p.push(value) {
+                    value = v;
+                    thread::yield_now();
+                }
+            }
+        });
+
+        for i in 0..nmsgs {
+            assert_eq!(c.recv().unwrap(), i);
+        }
+        producer.join().unwrap();
+        assert!(c.recv().is_err());
+    }
+}
@@ -0,0 +1,349 @@
+//! A lock-free stack.
+//!
+//! This is a Treiber stack. Popped nodes are reclaimed using hazard pointers:
+//! a thread announces the node it is about to read, and retired nodes are only
+//! freed once no thread has them announced. That also rules out the ABA
+//! problem, since a node's address cannot be reused while anyone may still
+//! compare against it.
+
+use std::mem::ManuallyDrop;
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize};
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Release, Relaxed};
+
+// Retired nodes are scanned for reclamation once this many have built up.
+const RECLAIM_THRESHOLD: usize = 64;
+
+struct Node<T> {
+    value: ManuallyDrop<T>,
+    // Set before the node is pushed and never changed afterwards.
+    next: *mut Node<T>,
+    // Link in the retired list, once the node has been popped.
+    retired_next: *mut Node<T>,
+}
+
+// A hazard pointer. Records are never freed while the stack is alive, a
+// thread takes an inactive one for the duration of each pop.
+struct Hazard<T> {
+    active: AtomicBool,
+    ptr: AtomicPtr<Node<T>>,
+    next: *mut Hazard<T>,
+}
+
+struct StackInner<T> {
+    head: AtomicPtr<Node<T>>,
+    hazards: AtomicPtr<Hazard<T>>,
+    retired: AtomicPtr<Node<T>>,
+    retired_count: AtomicUsize,
+}
+
+unsafe impl<T: Send> Send for StackInner<T> { }
+unsafe impl<T: Send> Sync for StackInner<T> { }
+
+impl<T> StackInner<T> {
+    fn new() -> StackInner<T> {
+        StackInner {
+            head: AtomicPtr::new(ptr::null_mut()),
+            hazards: AtomicPtr::new(ptr::null_mut()),
+            retired: AtomicPtr::new(ptr::null_mut()),
+            retired_count: AtomicUsize::new(0),
+        }
+    }
+
+    fn acquire_hazard(&self) -> &Hazard<T> {
+        unsafe {
+            let mut cur = self.hazards.load(Acquire);
+            while !cur.is_null() {
+                if !(*cur).active.load(Relaxed) &&
+                    (*cur).active.compare_exchange(false, true, Acquire, Relaxed).is_ok() {
+                    return &*cur
+                }
+                cur = (*cur).next;
+            }
+
+            // Every record is in use, add a new one.
+            let hazard = Box::into_raw(Box::new(Hazard {
+                active: AtomicBool::new(true),
+                ptr: AtomicPtr::new(ptr::null_mut()),
+                next: ptr::null_mut(),
+            }));
+            let mut head = self.hazards.load(Relaxed);
+            loop {
+                (*hazard).next = head;
+                match self.hazards.compare_exchange(head, hazard, AcqRel, Relaxed) {
+                    Ok(_) => return &*hazard,
+                    Err(h) => head = h,
+                }
+            }
+        }
+    }
+
+    // Load the head and announce it in `hazard`, so it is not freed until the
+    // hazard is announced again or released.
+    fn protect_head(&self, hazard: &Hazard<T>) -> *mut Node<T> {
+        loop {
+            let head = self.head.load(Acquire);
+            if head.is_null() {
+                return head
+            }
+
+            // Announce the node, then make sure it was not popped (and possibly
+            // freed) before the announcement was visible. The announcement is
+            // a swap to pair with the scan in reclaim. If the node was freed
+            // and its address reused in between, only the second load points
+            // at the new node, so that is the pointer returned.
+            hazard.ptr.swap(head, AcqRel);
+            let cur = self.head.load(Acquire);
+            if cur == head {
+                return cur
+            }
+        }
+    }
+
+    fn release_hazard(&self, hazard: &Hazard<T>) {
+        // A swap rather than a store, so a scan before it still orders with
+        // the next announcement in this record.
+        hazard.ptr.swap(ptr::null_mut(), Release);
+        hazard.active.store(false, Release);
+    }
+
+    fn push_retired(&self, node: *mut Node<T>) {
+        unsafe {
+            let mut head = self.retired.load(Relaxed);
+            loop {
+                (*node).retired_next = head;
+                match self.retired.compare_exchange(head, node, Release, Relaxed) {
+                    Ok(_) => return,
+                    Err(h) => head = h,
+                }
+            }
+        }
+    }
+
+    fn retire(&self, node: *mut Node<T>) {
+        // Counted before it is pushed, as another thread may reclaim it, and
+        // take it off the count, as soon as it is on the list.
+        let retired = self.retired_count.fetch_add(1, Relaxed) + 1;
+        self.push_retired(node);
+        if retired >= RECLAIM_THRESHOLD {
+            self.reclaim();
+        }
+    }
+
+    // Free every retired node that is not currently protected by a hazard
+    // pointer. Protected nodes go back on the retired list.
+    fn reclaim(&self) {
+        unsafe {
+            let mut cur = self.retired.swap(ptr::null_mut(), Acquire);
+            if cur.is_null() {
+                return
+            }
+
+            // Each hazard, and the list of them, is read with a read-modify-write
+            // rather than a load. Either the scan sees an announcement, or the
+            // announcing thread comes after it and so sees every node retired
+            // so far unlinked from the head. An inactive record holds null.
+            let mut protected = Vec::new();
+            let mut hazard = self.hazards.fetch_update(AcqRel, Acquire, Some).unwrap();
+            while !hazard.is_null() {
+                let p = (*hazard).ptr.fetch_update(AcqRel, Acquire, Some).unwrap();
+                if !p.is_null() {
+                    protected.push(p);
+                }
+                hazard = (*hazard).next;
+            }
+
+            while !cur.is_null() {
+                let next = (*cur).retired_next;
+                if protected.contains(&cur) {
+                    self.push_retired(cur);
+                } else {
+                    self.retired_count.fetch_sub(1, Relaxed);
+                    let _: Box<Node<T>> = Box::from_raw(cur);
+                }
+                cur = next;
+            }
+        }
+    }
+}
+
+impl<T: Send> StackInner<T> {
+    fn push(&self, value: T) {
+        let node = Box::into_raw(Box::new(Node {
+            value: ManuallyDrop::new(value),
+            next: ptr::null_mut(),
+            retired_next: ptr::null_mut(),
+        }));
+
+        // The head is protected while the node is linked to it. Otherwise it
+        // could be popped, freed and its address reused before the CAS, which
+        // would then succeed and leave `next` pointing at the freed node.
+        let hazard = self.acquire_hazard();
+        loop {
+            let head = self.protect_head(hazard);
+            unsafe { (*node).next = head; }
+            if self.head.compare_exchange(head, node, Release, Relaxed).is_ok() {
+                break
+            }
+        }
+        self.release_hazard(hazard);
+    }
+
+    fn pop(&self) -> Option<T> {
+        let hazard = self.acquire_hazard();
+        let popped = loop {
+            let head = self.protect_head(hazard);
+            if head.is_null() {
+                break None
+            }
+
+            let next = unsafe { (*head).next };
+            if self.head.compare_exchange(head, next, Acquire, Relaxed).is_ok() {
+                break Some(head)
+            }
+        };
+        self.release_hazard(hazard);
+
+        popped.map(|node| unsafe {
+            // Only the thread that unlinked the node reads its value, and the
+            // node never drops it.
+            let value = ptr::read(&*(*node).value);
+            self.retire(node);
+            value
+        })
+    }
+}
+
+impl<T> Drop for StackInner<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let mut cur = *self.head.get_mut();
+            while !cur.is_null() {
+                let mut node: Box<Node<T>> = Box::from_raw(cur);
+                ManuallyDrop::drop(&mut node.value);
+                cur = node.next;
+            }
+
+            let mut cur = *self.retired.get_mut();
+            while !cur.is_null() {
+                let node: Box<Node<T>> = Box::from_raw(cur);
+                cur = node.retired_next;
+            }
+
+            let mut cur = *self.hazards.get_mut();
+            while !cur.is_null() {
+                let hazard: Box<Hazard<T>> = Box::from_raw(cur);
+                cur = hazard.next;
+            }
+        }
+    }
+}
+
+/// A lock-free LIFO stack that is safe for push/pop on multiple threads.
+///
+/// Cloning a LockFreeStack gives another handle to the same stack.
+pub struct LockFreeStack<T> {
+    inner: Arc<StackInner<T>>,
+}
+
+impl<T> LockFreeStack<T> {
+    /// Create a new LockFreeStack.
+    pub fn new() -> LockFreeStack<T> {
+        LockFreeStack {
+            inner: Arc::new(StackInner::new()),
+        }
+    }
+
+    /// Returns true if the stack is empty.
+    pub fn is_empty(&self) -> bool {
+        self.inner.head.load(Acquire).is_null()
+    }
+}
+
+impl<T> Default for LockFreeStack<T> {
+    fn default() -> LockFreeStack<T> {
+        LockFreeStack::new()
+    }
+}
+
+impl<T: Send> LockFreeStack<T> {
+    /// Push a value onto the stack.
+    pub fn push(&self, value: T) {
+        self.inner.push(value)
+    }
+
+    /// Pop the most recently pushed value off the stack.
+    ///
+    /// If the stack is empty, None is returned.
+    pub fn pop(&self) -> Option<T> {
+        self.inner.pop()
+    }
+}
+
+impl<T: Send> Clone for LockFreeStack<T> {
+    fn clone(&self) -> LockFreeStack<T> {
+        LockFreeStack { inner: self.inner.clone() }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LockFreeStack;
+    use std::sync::Arc;
+    use std::thread::spawn;
+
+    #[test]
+    fn test_push_pop() {
+        let s = LockFreeStack::new();
+        assert!(s.pop().is_none());
+        s.push(1);
+        s.push(2);
+        assert_eq!(s.pop().unwrap(), 2);
+        assert_eq!(s.pop().unwrap(), 1);
+        assert!(s.is_empty());
+    }
+
+    #[test]
+    fn test_drops_values() {
+        let value = Arc::new(());
+        let s = LockFreeStack::new();
+        for _ in 0..200 {
+            s.push(value.clone());
+        }
+        for _ in 0..100 {
+            assert!(s.pop().is_some());
+        }
+        assert_eq!(Arc::strong_count(&value), 101);
+        drop(s);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_concurrent() {
+        let s = LockFreeStack::new();
+        let mut guard_vec = Vec::new();
+        for t in 0..8 {
+            let st = s.clone();
+            guard_vec.push(spawn(move || {
+                let mut popped = 0;
+                for i in 0..1000 {
+                    st.push(t * 1000 + i);
+                    if st.pop().is_some() {
+                        popped += 1;
+                    }
+                }
+                popped
+            }));
+        }
+
+        let mut popped = 0;
+        for thr in guard_vec.into_iter() {
+            popped += thr.join().unwrap();
+        }
+        while s.pop().is_some() {
+            popped += 1;
+        }
+        assert_eq!(popped, 8000);
+    }
+}
@@ -0,0 +1,178 @@
+//! Strategies for how a blocking operation waits.
+//!
+//! A blocking operation retries until it can complete, calling
+//! `WaitStrategy::wait` between attempts. The strategy trades latency for CPU:
+//! busy-spinning reacts fastest but burns a core, parking sleeps until woken.
+//!
+//! The mpmc channel uses the strategy set with `mpmc::Builder::wait_strategy`
+//! both for `Receiver::recv` and for `Sender::send_wait`.
+
+use std::hint;
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::SeqCst;
+use std::thread;
+use std::time::Duration;
+
+/// How a blocking operation waits between attempts.
+pub trait WaitStrategy: Send + Sync {
+    /// Wait before retrying an operation that could not complete.
+    ///
+    /// `attempt` is the number of times the operation has already waited, it
+    /// starts at 0. `ready` returns true once the operation may be able to
+    /// complete. A strategy that sleeps must only check it after it has
+    /// registered itself as waiting, so that a `notify` from the thread that
+    /// made it true cannot be missed.
+    fn wait(&self, attempt: usize, ready: &dyn Fn() -> bool);
+
+    /// Wake threads that are waiting.
+    ///
+    /// This is called after any operation that may let a waiting thread make
+    /// progress. Strategies that never sleep do not need to do anything.
+    fn notify(&self) {}
+}
+
+/// Spin on the CPU without ever giving up the thread.
+///
+/// This gives the lowest latency, at the cost of a fully busy core per waiting
+/// thread.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BusySpin;
+
+impl WaitStrategy for BusySpin {
+    fn wait(&self, _attempt: usize, _ready: &dyn Fn() -> bool) {
+        hint::spin_loop();
+    }
+}
+
+/// Yield the thread to the scheduler between attempts.
+///
+/// This is the default strategy.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Yield;
+
+impl WaitStrategy for Yield {
+    fn wait(&self, _attempt: usize, _ready: &dyn Fn() -> bool) {
+        thread::yield_now();
+    }
+}
+
+/// Spin for a number of attempts, then yield the thread.
+#[derive(Clone, Copy, Debug)]
+pub struct SpinThenYield {
+    spins: usize,
+}
+
+impl SpinThenYield {
+    /// Spin for `spins` attempts before starting to yield.
+    pub fn new(spins: usize) -> SpinThenYield {
+        SpinThenYield { spins }
+    }
+}
+
+impl Default for SpinThenYield {
+    fn default() -> SpinThenYield {
+        SpinThenYield::new(100)
+    }
+}
+
+impl WaitStrategy for SpinThenYield {
+    fn wait(&self, attempt: usize, _ready: &dyn Fn() -> bool) {
+        if attempt < self.spins {
+            hint::spin_loop();
+        } else {
+            thread::yield_now();
+        }
+    }
+}
+
+/// Park the thread until it is notified.
+///
+/// A thread sleeps for at most `timeout` at a time, as a backstop. It is
+/// normally woken by `notify` as soon as it can make progress.
+#[derive(Debug)]
+pub struct Park {
+    // Only changed with `lock` held, but read without it by `notify`.
+    waiters: AtomicUsize,
+    lock: Mutex<()>,
+    cond: Condvar,
+    timeout: Duration,
+}
+
+impl Park {
+    /// Park for at most `timeout` at a time.
+    pub fn new(timeout: Duration) -> Park {
+        Park {
+            waiters: AtomicUsize::new(0),
+            lock: Mutex::new(()),
+            cond: Condvar::new(),
+            timeout,
+        }
+    }
+}
+
+impl Default for Park {
+    fn default() -> Park {
+        Park::new(Duration::from_millis(1))
+    }
+}
+
+impl WaitStrategy for Park {
+    fn wait(&self, _attempt: usize, ready: &dyn Fn() -> bool) {
+        let guard = self.lock.lock().unwrap_or_else(PoisonError::into_inner);
+        // Register before checking `ready`. A notifier makes the operation
+        // ready before loading `waiters`, so either it sees this waiter and
+        // takes the lock to notify, which cannot happen until the wait below
+        // has released it, or `ready` sees its change.
+        self.waiters.fetch_add(1, SeqCst);
+        let guard = if ready() {
+            guard
+        } else {
+            self.cond.wait_timeout(guard, self.timeout).unwrap_or_else(PoisonError::into_inner).0
+        };
+        self.waiters.fetch_sub(1, SeqCst);
+        drop(guard);
+    }
+
+    fn notify(&self) {
+        // Skip the lock entirely when nobody is parked.
+        if self.waiters.load(SeqCst) == 0 {
+            return
+        }
+        let _guard = self.lock.lock().unwrap_or_else(PoisonError::into_inner);
+        self.cond.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering::SeqCst;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use super::{Park, WaitStrategy};
+
+    #[test]
+    fn test_park_notify() {
+        let park = Arc::new(Park::new(Duration::from_secs(10)));
+        let ready = Arc::new(AtomicBool::new(false));
+        let waiter = {
+            let park = park.clone();
+            let ready = ready.clone();
+            thread::spawn(move || {
+                let start = Instant::now();
+                while !ready.load(SeqCst) {
+                    park.wait(0, &|| ready.load(SeqCst));
+                }
+                start.elapsed()
+            })
+        };
+
+        // A single notification is enough, even if it comes before the waiter
+        // has parked.
+        ready.store(true, SeqCst);
+        park.notify();
+        assert!(waiter.join().unwrap() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,291 @@
+//! A single-value channel for sharing the latest state.
+//!
+//! The Sender replaces the current value, and Receivers read whichever value is
+//! current. Updates are not queued: each update bumps a version number, and a
+//! Receiver only finds out that the value changed since it last looked, not how
+//! many times.
+
+use std::any::Any;
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+/// Error from watch module.
+pub enum WatchError<T> {
+    /// There were no receivers, the value was not sent.
+    SendError(T),
+    /// The sender is gone and there are no more changes to see.
+    Disconnected,
+}
+
+impl<T: fmt::Display> fmt::Display for WatchError<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WatchError::SendError(ref t) =>
+                write!(fmt, "could not send data on channel: {}", t),
+            WatchError::Disconnected =>
+                write!(fmt, "channel is disconnected"),
+        }
+    }
+}
+
+impl<T: Send + fmt::Display + fmt::Debug + Any> Error for WatchError<T> {
+    fn description(&self) -> &str {
+        match *self {
+            WatchError::SendError(_) => "could not send data on channel",
+            WatchError::Disconnected => "channel is disconnected",
+        }
+    }
+
+    fn cause(&self) -> Option<&dyn Error> {
+        None
+    }
+}
+
+// The value and its version are replaced together, so a receiver never pairs
+// a value with the version of another.
+struct Versioned<T> {
+    value: T,
+    // Bumped by every send.
+    version: u64,
+}
+
+struct Shared<T> {
+    value: RwLock<Versioned<T>>,
+    // Set once the sender is dropped.
+    closed: Mutex<bool>,
+    // Signalled, with `closed` held, when the version changes or the sender is
+    // dropped.
+    changed: Condvar,
+    receivers: AtomicUsize,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, bool> {
+        self.closed.lock().unwrap()
+    }
+
+    fn borrow(&self) -> Ref<'_, T> {
+        Ref { guard: self.value.read().unwrap() }
+    }
+
+    fn version(&self) -> u64 {
+        self.value.read().unwrap().version
+    }
+}
+
+/// A reference to the current value of a watch channel.
+///
+/// The Sender cannot replace the value while a Ref is held, so it should not be
+/// kept for long.
+pub struct Ref<'a, T> {
+    guard: RwLockReadGuard<'a, Versioned<T>>,
+}
+
+impl<'a, T> Deref for Ref<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard.value
+    }
+}
+
+/// Replaces the value of a watch channel.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Replace the current value and wake every Receiver waiting in `changed`.
+    ///
+    /// If there are no receivers, the value is returned in the Err(..) and the
+    /// current value is left as it is.
+    pub fn send(&self, value: T) -> Result<(), WatchError<T>> {
+        if self.shared.receivers.load(Ordering::SeqCst) == 0 {
+            return Err(WatchError::SendError(value))
+        }
+
+        {
+            let mut current = self.shared.value.write().unwrap();
+            current.value = value;
+            current.version += 1;
+        }
+        // Taking the lock means a receiver that saw the old version is already
+        // waiting, and gets this notification.
+        let _closed = self.shared.lock();
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    /// Borrow the current value.
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.shared.borrow()
+    }
+
+    /// Create a Receiver that sees the current value as already seen.
+    pub fn subscribe(&self) -> Receiver<T> {
+        Receiver::new(self.shared.clone(), self.shared.version())
+    }
+
+    /// Returns the number of receivers.
+    pub fn receiver_count(&self) -> usize {
+        self.shared.receivers.load(Ordering::SeqCst)
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        *self.shared.lock() = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+/// Reads the value of a watch channel.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    // The version this receiver last saw.
+    seen: Cell<u64>,
+}
+
+impl<T> Receiver<T> {
+    fn new(shared: Arc<Shared<T>>, seen: u64) -> Receiver<T> {
+        shared.receivers.fetch_add(1, Ordering::SeqCst);
+        Receiver { shared, seen: Cell::new(seen) }
+    }
+
+    /// Borrow the current value.
+    ///
+    /// This does not mark the value as seen, see `borrow_and_update`.
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.shared.borrow()
+    }
+
+    /// Borrow the current value and mark it as seen.
+    ///
+    /// `changed` then waits for a value newer than the one borrowed.
+    pub fn borrow_and_update(&self) -> Ref<'_, T> {
+        let current = self.shared.borrow();
+        self.seen.set(current.guard.version);
+        current
+    }
+
+    /// Returns true if the value has been replaced since this receiver last
+    /// saw it.
+    pub fn has_changed(&self) -> bool {
+        self.shared.version() != self.seen.get()
+    }
+
+    /// Block until the value has been replaced since this receiver last saw
+    /// it, then mark the new value as seen.
+    ///
+    /// Returns immediately if the value already changed. Once the Sender is
+    /// dropped and the latest value has been seen, `WatchError::Disconnected`
+    /// is returned.
+    pub fn changed(&self) -> Result<(), WatchError<T>> {
+        let mut closed = self.shared.lock();
+        loop {
+            let version = self.shared.version();
+            if version != self.seen.get() {
+                self.seen.set(version);
+                return Ok(())
+            }
+            if *closed {
+                return Err(WatchError::Disconnected)
+            }
+            closed = self.shared.changed.wait(closed).unwrap();
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    /// The new receiver has seen the same version as this one.
+    fn clone(&self) -> Receiver<T> {
+        Receiver::new(self.shared.clone(), self.seen.get())
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receivers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Create a (Sender<T>, Receiver<T>) pair holding `init`.
+///
+/// The receiver sees `init` as already seen.
+pub fn watch_channel<T>(init: T) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        value: RwLock::new(Versioned { value: init, version: 0 }),
+        closed: Mutex::new(false),
+        changed: Condvar::new(),
+        receivers: AtomicUsize::new(0),
+    });
+    let receiver = Receiver::new(shared.clone(), 0);
+    (Sender { shared }, receiver)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{watch_channel, WatchError};
+    use std::thread::{sleep, spawn};
+    use std::time::Duration;
+
+    #[test]
+    fn latest_value_only() {
+        let (tx, rx) = watch_channel(0u8);
+        assert_eq!(*rx.borrow(), 0);
+        assert!(!rx.has_changed());
+
+        assert!(tx.send(1).is_ok());
+        assert!(tx.send(2).is_ok());
+        assert!(rx.has_changed());
+        assert_eq!(rx.changed(), Ok(()));
+        assert_eq!(*rx.borrow(), 2);
+        assert!(!rx.has_changed());
+
+        let rx2 = tx.subscribe();
+        assert_eq!(tx.receiver_count(), 2);
+        assert!(!rx2.has_changed());
+        drop(rx);
+        drop(rx2);
+        assert_eq!(tx.send(3), Err(WatchError::SendError(3)));
+        assert_eq!(*tx.borrow(), 2);
+    }
+
+    #[test]
+    fn borrow_and_update() {
+        let (tx, rx) = watch_channel(0u8);
+        assert!(tx.send(1).is_ok());
+        assert_eq!(*rx.borrow(), 1);
+        assert!(rx.has_changed());
+        assert_eq!(*rx.borrow_and_update(), 1);
+        assert!(!rx.has_changed());
+
+        assert!(tx.send(2).is_ok());
+        assert_eq!(rx.changed(), Ok(()));
+        assert_eq!(*rx.borrow_and_update(), 2);
+        drop(tx);
+        assert_eq!(rx.changed(), Err(WatchError::Disconnected));
+    }
+
+    #[test]
+    fn changed_blocks_until_send() {
+        let (tx, rx) = watch_channel(String::from("starting"));
+        let watcher = spawn(move || {
+            let mut seen = Vec::new();
+            while rx.changed().is_ok() {
+                seen.push(rx.borrow().clone());
+            }
+            seen
+        });
+
+        sleep(Duration::from_millis(10));
+        assert!(tx.send(String::from("healthy")).is_ok());
+        drop(tx);
+        assert_eq!(watcher.join().unwrap(), vec![String::from("healthy")]);
+    }
+}